@@ -1,14 +1,21 @@
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::Arc;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use glob::glob;
 use structopt::StructOpt;
 
-use json_to_tables::database::{Database, DatabaseCsv, DatabaseSchema};
-use json_to_tables::read::read_to_db_many;
+use json_to_tables::database::naming::{naming_strategy, NamingStrategy, SeparatorNaming};
+use json_to_tables::database::{
+    ddl, formats, open_database, Database, DatabaseSchema, Dialect, FormatOptions, SchemaConfig,
+};
+use json_to_tables::read::{
+    read_to_db_many_parallel, read_to_db_many_tolerant, ReadOptions, Reject, DEFAULT_CHUNK_SIZE,
+};
+use json_to_tables::Error;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -18,17 +25,131 @@ json tables with foreign keys"
 )]
 struct Cli {
     /// Output directory path
-    output: std::path::PathBuf,
+    #[structopt(required_unless("list-formats"))]
+    output: Option<PathBuf>,
 
-    /// Source .json files to convert to file tables structure
+    /// Source .json files to convert to file tables structure,
+    /// files all being .jsonl or .ndjson turn on --json-lines
     files: Vec<String>,
+
+    /// Schema.json of a previous run to keep table names and column positions stable
+    #[structopt(long)]
+    schema: Option<PathBuf>,
+
+    /// Json config with root table name and table name overrides, e.g.
+    /// {"root_name": "store", "table_names": [{"path": [["books"]], "name": "books"}]}
+    #[structopt(long)]
+    config: Option<PathBuf>,
+
+    /// Naming of tables and columns: escaped, dotted, underscored or custom.
+    /// Underscored joins keys with `__` and tables with `___` but keeps keys as they are,
+    /// as converting them to snake_case could give different keys the same name
+    #[structopt(long, default_value = "escaped")]
+    naming: String,
+
+    /// Separator of nested keys in column names for custom naming
+    #[structopt(long, required_if("naming", "custom"))]
+    separator: Option<String>,
+
+    /// Separator of nested tables in table names for custom naming
+    #[structopt(long, required_if("naming", "custom"))]
+    table_separator: Option<String>,
+
+    /// Database whose identifier rules names must follow:
+    /// generic, postgres, mysql, sqlite, bigquery or snowflake [default: generic]
+    #[structopt(long)]
+    dialect: Option<Dialect>,
+
+    /// Output format, see --list-formats. Several formats separated by commas
+    /// are written at once, each into a subdirectory named after the format
+    #[structopt(long, default_value = "csv")]
+    format: String,
+
+    /// Format specific option as key=value, may be repeated. With several
+    /// formats, format.key=value applies to one of them only
+    #[structopt(short = "O", long = "format-option")]
+    format_options: Vec<String>,
+
+    /// Write create_tables.sql for --dialect and, for csv format, a script loading the files
+    #[structopt(long)]
+    ddl: bool,
+
+    /// Number of threads parsing files, output does not depend on it. With several
+    /// jobs, files holding a top-level array are also split and parsed in chunks
+    #[structopt(short = "j", long, default_value = "1")]
+    jobs: usize,
+
+    /// Approximate size in bytes of chunks files are split into with several jobs
+    #[structopt(long)]
+    chunk_size: Option<usize>,
+
+    /// Files hold json documents one after another, e.g. one per line,
+    /// each one making a record of the root table
+    #[structopt(long)]
+    json_lines: bool,
+
+    /// Skip malformed records of json lines and top-level arrays, writing them
+    /// with their file, byte offset and error as json lines to this file
+    #[structopt(long)]
+    rejects: Option<PathBuf>,
+
+    /// Fail once more than this number of records is rejected
+    #[structopt(long, requires("rejects"))]
+    max_rejects: Option<usize>,
+
+    /// Print available output formats with their options and exit
+    #[structopt(long)]
+    list_formats: bool,
+}
+
+fn make_naming(opt: &Cli) -> Result<Arc<dyn NamingStrategy>> {
+    if opt.naming == "custom" {
+        return Ok(Arc::new(SeparatorNaming::new(
+            opt.separator.as_deref().unwrap_or_default(),
+            opt.table_separator.as_deref().unwrap_or_default(),
+        )?));
+    }
+    naming_strategy(&opt.naming).ok_or_else(|| anyhow!("Unknown naming {}", opt.naming))
+}
+
+/// Extensions of files holding json lines
+const JSON_LINES_EXTENSIONS: &[&str] = &["jsonl", "ndjson"];
+
+fn is_json_lines(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| JSON_LINES_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn print_formats() {
+    for format in formats() {
+        println!("{} - {}", format.name, format.description);
+        for (name, description) in format.options {
+            println!("    -O {}=... - {}", name, description);
+        }
+    }
 }
 
-fn _path_to_str(p: &PathBuf) -> String {
-    match p.clone().into_os_string().into_string() {
-        Ok(s) => s,
-        Err(_) => String::from("Unknown path"),
+fn write_ddl(schema: &DatabaseSchema, format: &str, output: &Path) -> Result<()> {
+    let path = output.join("create_tables.sql");
+    std::fs::write(&path, ddl::create_tables(schema)?)
+        .with_context(|| format!("Could not write file {}", path.to_string_lossy()))?;
+
+    // Several formats are written to a subdirectory each
+    let formats = format.split(',').collect::<Vec<_>>();
+    if formats.contains(&"csv") {
+        let file_name = match schema.dialect() {
+            Dialect::Bigquery => "load.sh",
+            _ => "load.sql",
+        };
+        let path = match formats.len() {
+            1 => output.join(file_name),
+            _ => output.join("csv").join(file_name),
+        };
+        std::fs::write(&path, ddl::load_script(schema)?)
+            .with_context(|| format!("Could not write file {}", path.to_string_lossy()))?;
     }
+    Ok(())
 }
 
 fn open_files(files: Vec<String>) -> Result<Vec<(PathBuf, BufReader<File>)>> {
@@ -41,10 +162,7 @@ fn open_files(files: Vec<String>) -> Result<Vec<(PathBuf, BufReader<File>)>> {
             match entry {
                 Ok(path_buf) => {
                     let file = File::open(path_buf.clone().as_path()).with_context(|| {
-                        format!(
-                            "Could not read file {}",
-                            path_buf.to_string_lossy().to_string()
-                        )
+                        format!("Could not read file {}", path_buf.to_string_lossy())
                     })?;
                     all_files.push((path_buf, BufReader::new(file)));
                 }
@@ -59,30 +177,149 @@ fn open_files(files: Vec<String>) -> Result<Vec<(PathBuf, BufReader<File>)>> {
     Ok(all_files)
 }
 
+/// Add file, and line and column if known, to error of reading `path`
+fn locate_error(error: Error, path: &Path) -> anyhow::Error {
+    let location = error.location();
+    let context = match location {
+        Some(location) if location.pointer.is_empty() => format!(
+            "{}:{}:{}",
+            path.to_string_lossy(),
+            location.position.line,
+            location.position.column
+        ),
+        Some(location) => format!(
+            "{}:{}:{} at {}",
+            path.to_string_lossy(),
+            location.position.line,
+            location.position.column,
+            location.pointer
+        ),
+        None => format!("Could not read {}", path.to_string_lossy()),
+    };
+    anyhow::Error::from(error).context(context)
+}
+
 fn main() -> Result<()> {
     let opt = Cli::from_args();
 
-    if opt.files.len() == 0 {
+    if opt.list_formats {
+        print_formats();
+        return Ok(());
+    }
+    if opt.files.is_empty() {
         bail!("Must provide at least one file")
     }
-    let db_schema = DatabaseSchema::empty();
+    if opt.ddl && opt.dialect.is_none() {
+        bail!("Option --ddl requires --dialect")
+    }
+    let mut db_schema = match &opt.schema {
+        Some(path) => DatabaseSchema::load_file(path)?,
+        None => DatabaseSchema::empty(),
+    };
+    db_schema.set_naming(make_naming(&opt)?);
+    // Schema of a previous run keeps its dialect unless asked otherwise
+    if let Some(dialect) = opt.dialect {
+        db_schema.set_dialect(dialect);
+    }
+    if let Some(path) = &opt.config {
+        db_schema.configure(SchemaConfig::load_file(path)?)?;
+    }
 
-    let mut db = DatabaseCsv::new(db_schema, opt.output)?;
+    let output = opt.output.unwrap_or_default();
+    let mut format_options = FormatOptions::new(output.clone());
+    for option in opt.format_options.iter() {
+        format_options.push(option)?;
+    }
+    let mut db = open_database(&opt.format, db_schema, &format_options)?;
     let all_files = open_files(opt.files)?;
+    let lines_files = all_files
+        .iter()
+        .filter(|(path, _)| is_json_lines(path))
+        .count();
+    if !opt.json_lines && lines_files > 0 && lines_files < all_files.len() {
+        bail!("Files mix json lines and other json, pass --json-lines to read all of them as json lines")
+    }
+    let json_lines = opt.json_lines || lines_files > 0;
 
-    fn callback_success(path: PathBuf, num_records: usize) {
+    let paths: Vec<PathBuf> = all_files.iter().map(|(path, _)| path.clone()).collect();
+    let mut num_parsed: usize = 0;
+    let mut callback_success = |path: PathBuf, num_records: usize| {
+        num_parsed += 1;
         println!(
             "Parsed {} - {} records",
             path.to_string_lossy(),
             num_records
         );
-    }
+    };
 
     // Write data
-    read_to_db_many(&mut db, all_files, &mut callback_success)?;
+    let read_options = ReadOptions {
+        jobs: opt.jobs,
+        chunk_size: opt.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE),
+        json_lines,
+    };
+    let result = match &opt.rejects {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Could not create file {}", path.to_string_lossy()))?;
+            let mut writer = BufWriter::new(file);
+            let mut num_rejects: usize = 0;
+            let mut write_reject = |file: &PathBuf, reject: Reject| -> Result<()> {
+                num_rejects += 1;
+                let line = serde_json::json!({
+                    "file": file.to_string_lossy(),
+                    "offset": reject.position.offset,
+                    "line": reject.position.line,
+                    "column": reject.position.column,
+                    "error": reject.error,
+                    "record": String::from_utf8_lossy(&reject.bytes),
+                });
+                serde_json::to_writer(&mut writer, &line)?;
+                writer.write_all(b"\n")?;
+                match opt.max_rejects {
+                    Some(max) if num_rejects > max => {
+                        bail!(
+                            "More than {} records rejected, see {}",
+                            max,
+                            path.to_string_lossy()
+                        )
+                    }
+                    _ => Ok(()),
+                }
+            };
+            let result = read_to_db_many_tolerant(
+                &mut db,
+                all_files,
+                &read_options,
+                &mut write_reject,
+                &mut callback_success,
+            );
+            if result.is_ok() {
+                writer.flush().context("Could not write rejects")?;
+                println!(
+                    "Rejected {} records, see {}",
+                    num_rejects,
+                    path.to_string_lossy()
+                );
+            }
+            result
+        }
+        None => read_to_db_many_parallel(&mut db, all_files, &read_options, &mut callback_success),
+    };
+    // Files are read in order, the one that failed is the first one not parsed
+    if let Err(e) = result {
+        return Err(match paths.get(num_parsed) {
+            Some(path) => locate_error(e, path),
+            None => e.into(),
+        });
+    }
 
     // Close database
     db.close()?;
 
+    if opt.ddl {
+        write_ddl(db.get_schema(), &opt.format, &output)?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,308 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::mem::swap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use arrow_array::builder::{
+    BooleanBuilder, Float64Builder, Int32Builder, Int64Builder, StringBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use serde_json::Value;
+
+use crate::database::{ColumnSchema, DatabaseSchema, TableSchema, ValueType};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{ensure_dir_exists_and_empty, row_values, write_schema};
+use super::Database;
+
+/// Number of rows in record batch unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy)]
+pub struct ArrowOptions {
+    /// Maximum number of rows in record batch
+    pub batch_size: usize,
+}
+
+impl Default for ArrowOptions {
+    fn default() -> Self {
+        ArrowOptions {
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+/// Arrow field of column, keys are non-nullable 32 bit integers
+pub fn column_field(col: &ColumnSchema) -> Field {
+    match col {
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => {
+            Field::new(col.name(), DataType::Int32, false)
+        }
+        ColumnSchema::SourceColumn(col) => {
+            let data_type = match col.value_type() {
+                ValueType::Bool => DataType::Boolean,
+                ValueType::Integer => DataType::Int64,
+                ValueType::Float => DataType::Float64,
+                ValueType::Null | ValueType::String => DataType::Utf8,
+            };
+            Field::new(&col.name, data_type, true)
+        }
+    }
+}
+
+/// Arrow schema of table with columns in the order of `schema`
+pub fn arrow_schema(schema: &TableSchema) -> SchemaRef {
+    Arc::new(Schema::new(
+        schema.columns.iter().map(column_field).collect::<Vec<_>>(),
+    ))
+}
+
+/// Build array of column `col_id` of `rows`, values of other types are rendered as strings
+fn build_column(col: &ColumnSchema, col_id: usize, rows: &[Vec<Value>]) -> ArrayRef {
+    let values = rows
+        .iter()
+        .map(|row| row.get(col_id).unwrap_or(&Value::Null));
+
+    let value_type = match col {
+        ColumnSchema::SourceColumn(col) => col.value_type(),
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => {
+            let mut builder = Int32Builder::with_capacity(rows.len());
+            builder.extend(values.map(|v| v.as_i64().map(|v| v as i32)));
+            return Arc::new(builder.finish());
+        }
+    };
+    match value_type {
+        ValueType::Bool => {
+            let mut builder = BooleanBuilder::with_capacity(rows.len());
+            builder.extend(values.map(Value::as_bool));
+            Arc::new(builder.finish())
+        }
+        ValueType::Integer => {
+            let mut builder = Int64Builder::with_capacity(rows.len());
+            builder.extend(values.map(Value::as_i64));
+            Arc::new(builder.finish())
+        }
+        ValueType::Float => {
+            let mut builder = Float64Builder::with_capacity(rows.len());
+            builder.extend(values.map(Value::as_f64));
+            Arc::new(builder.finish())
+        }
+        ValueType::Null | ValueType::String => {
+            let mut builder = StringBuilder::new();
+            builder.extend(values.map(|v| match v {
+                Value::Null => None,
+                Value::String(s) => Some(s.clone()),
+                v => Some(v.to_string()),
+            }));
+            Arc::new(builder.finish())
+        }
+    }
+}
+
+/// Collects rows of a single table and turns them into record batches on close
+///
+/// Column types are only known once all records are seen, so rows are kept
+/// in memory until then.
+pub struct TableArrow {
+    rows: Vec<Vec<Value>>,
+    batches: Vec<RecordBatch>,
+    options: ArrowOptions,
+    // Buffer reused between rows
+    cells: Vec<Option<usize>>,
+    schema: Option<TableSchema>,
+}
+
+impl TableArrow {
+    pub fn new(schema: TableSchema, options: ArrowOptions) -> TableArrow {
+        TableArrow {
+            rows: Vec::new(),
+            batches: Vec::new(),
+            options,
+            cells: Vec::new(),
+            schema: Some(schema),
+        }
+    }
+
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+        let values = row_values(schema, row, &self.cells);
+        self.rows
+            .push(values.into_iter().map(Cow::into_owned).collect());
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let arrow_schema = arrow_schema(schema);
+        let rows = std::mem::take(&mut self.rows);
+        for chunk in rows.chunks(self.options.batch_size.max(1)) {
+            let columns = schema
+                .columns
+                .iter()
+                .enumerate()
+                .map(|(col_id, col)| build_column(col, col_id, chunk))
+                .collect::<Vec<_>>();
+            self.batches.push(
+                RecordBatch::try_new(arrow_schema.clone(), columns)
+                    .with_context(|| format!("Could not build batch of table {}", schema.name))?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Write batches as arrow ipc file
+    fn write_ipc(&self, data_path: &Path, arrow_schema: &SchemaRef) -> Result<()> {
+        let file = File::create(data_path)
+            .with_context(|| format!("Could not create file {}", data_path.to_string_lossy()))?;
+        let mut writer = FileWriter::try_new(file, arrow_schema)?;
+        for batch in self.batches.iter() {
+            writer.write(batch)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Collects tables as arrow record batches, e.g. to hand them to Polars or DataFusion
+///
+/// Batches are available through `batches` once the database is closed. When created
+/// with an output path, an arrow ipc (feather) file per table is also written there.
+pub struct DatabaseArrow {
+    schema: DatabaseSchema,
+    path: Option<PathBuf>,
+    options: ArrowOptions,
+    tables: HashMap<Vec<JsonPath>, TableArrow>,
+    // Table names with their arrow schema and batches, filled on close
+    batches: HashMap<String, (SchemaRef, Vec<RecordBatch>)>,
+}
+
+impl DatabaseArrow {
+    /// Keep batches in memory only
+    pub fn new(schema: DatabaseSchema, options: ArrowOptions) -> DatabaseArrow {
+        DatabaseArrow {
+            schema,
+            path: None,
+            options,
+            tables: HashMap::new(),
+            batches: HashMap::new(),
+        }
+    }
+
+    /// Also write `.arrow` ipc file per table and `schema.json` to `path` on close
+    pub fn with_ipc(
+        schema: DatabaseSchema,
+        path: PathBuf,
+        options: ArrowOptions,
+    ) -> Result<DatabaseArrow> {
+        let mut data_path = path.clone();
+        data_path.push("data");
+
+        ensure_dir_exists_and_empty(&data_path)?;
+
+        let mut db = DatabaseArrow::new(schema, options);
+        db.path = Some(path);
+        Ok(db)
+    }
+
+    /// Names of tables with batches
+    pub fn table_names(&self) -> impl Iterator<Item = &str> {
+        self.batches.keys().map(String::as_str)
+    }
+
+    /// Arrow schema and record batches of table `name`
+    pub fn batches(&self, name: &str) -> Option<(&SchemaRef, &[RecordBatch])> {
+        self.batches
+            .get(name)
+            .map(|(schema, batches)| (schema, batches.as_slice()))
+    }
+
+    /// Take all tables, mapping table names to their arrow schema and record batches
+    pub fn into_batches(self) -> HashMap<String, (SchemaRef, Vec<RecordBatch>)> {
+        self.batches
+    }
+
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableArrow> {
+        if !self.tables.contains_key(table_path) {
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+            self.tables.insert(
+                table_path.to_vec(),
+                TableArrow::new(table_schema, self.options),
+            );
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+}
+
+impl Database for DatabaseArrow {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(&loc.table_path)?;
+        table.write(loc, record).map_err(Error::from)
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row).map_err(Error::from)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        for (table_path, mut table) in self.tables.drain() {
+            table.close()?;
+            let table_schema = table
+                .pop_schema()
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+            let arrow_schema = arrow_schema(&table_schema);
+
+            if let Some(path) = self.path.as_ref() {
+                let mut data_path = path.clone();
+                data_path.push("data");
+                data_path.push(table_schema.name.clone() + ".arrow");
+                table.write_ipc(&data_path, &arrow_schema)?;
+            }
+
+            self.batches
+                .insert(table_schema.name.clone(), (arrow_schema, table.batches));
+            self.schema.return_table_schema(&table_path, table_schema)?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        match self.path.as_ref() {
+            Some(path) => write_schema(path, &self.schema).map_err(Error::from),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Write};
+use std::mem::swap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::database::{ColumnSchema, DatabaseSchema, TableSchema, ValueType};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{ensure_dir_exists_and_empty, row_values, write_schema, RowSpool};
+use super::Database;
+
+/// Number of rows in data block unless configured otherwise
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+
+const MAGIC: &[u8] = b"Obj\x01";
+
+#[derive(Clone, Copy)]
+pub struct AvroOptions {
+    /// Number of rows in data block of container file
+    pub block_size: usize,
+}
+
+impl Default for AvroOptions {
+    fn default() -> Self {
+        AvroOptions {
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+/// Avro name made of letters, digits and underscores, not starting with a digit
+fn avro_name(name: &str) -> String {
+    let mut avro_name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if !avro_name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        avro_name.insert(0, '_');
+    }
+    avro_name
+}
+
+/// Avro field names of columns, made unique after replacing invalid characters
+fn field_names(schema: &TableSchema) -> Vec<String> {
+    let mut used = HashSet::new();
+    schema
+        .columns
+        .iter()
+        .map(|col| {
+            let name = avro_name(col.name());
+            let mut unique = name.clone();
+            let mut suffix = 1;
+            while !used.insert(unique.clone()) {
+                suffix += 1;
+                unique = format!("{}_{}", name, suffix);
+            }
+            unique
+        })
+        .collect()
+}
+
+/// Primitive avro type of column and whether it is a union with null
+fn field_type(col: &ColumnSchema) -> (&'static str, bool) {
+    match col {
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => ("long", false),
+        ColumnSchema::SourceColumn(col) => {
+            let avro_type = match col.value_type() {
+                ValueType::Bool => "boolean",
+                ValueType::Integer => "long",
+                ValueType::Float => "double",
+                ValueType::Null | ValueType::String => "string",
+            };
+            (avro_type, col.is_nullable || col.is_null)
+        }
+    }
+}
+
+/// Avro record schema of table
+///
+/// Nullable columns are unions with null, defaulting to null. Field names have
+/// invalid characters replaced, the column name is kept in `doc`.
+pub fn avro_schema(schema: &TableSchema) -> Value {
+    let fields = schema
+        .columns
+        .iter()
+        .zip(field_names(schema))
+        .map(|(col, name)| match field_type(col) {
+            (avro_type, true) => json!({
+                "name": name,
+                "type": ["null", avro_type],
+                "default": null,
+                "doc": col.name(),
+            }),
+            (avro_type, false) => json!({
+                "name": name,
+                "type": avro_type,
+                "doc": col.name(),
+            }),
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "type": "record",
+        "name": avro_name(&schema.name),
+        "fields": fields,
+    })
+}
+
+/// Zigzag varint encoding of `long` and `int`
+fn encode_long(buf: &mut Vec<u8>, v: i64) {
+    let mut n = ((v << 1) ^ (v >> 63)) as u64;
+    while n >= 0x80 {
+        buf.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    encode_long(buf, bytes.len() as i64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Encode value of column, values of string columns are rendered as strings
+///
+/// Fails on values not representable in other column types, e.g. `1e400` as double.
+fn encode_value(buf: &mut Vec<u8>, col: &ColumnSchema, v: &Value) -> Result<()> {
+    let (avro_type, nullable) = field_type(col);
+    if nullable {
+        if v.is_null() {
+            encode_long(buf, 0);
+            return Ok(());
+        }
+        encode_long(buf, 1);
+    }
+    let unrepresentable = || {
+        let error = anyhow!(
+            "Value {} of column {} is not representable as avro {}",
+            v,
+            col.name(),
+            avro_type
+        );
+        Error::Sink(error)
+    };
+    match avro_type {
+        "boolean" => buf.push(u8::from(v.as_bool().ok_or_else(unrepresentable)?)),
+        "long" => encode_long(buf, v.as_i64().ok_or_else(unrepresentable)?),
+        "double" => {
+            let v = v
+                .as_f64()
+                .filter(|v| v.is_finite())
+                .ok_or_else(unrepresentable)?;
+            buf.extend_from_slice(&v.to_le_bytes())
+        }
+        _ => match v {
+            Value::String(s) => encode_bytes(buf, s.as_bytes()),
+            Value::Null => encode_bytes(buf, b""),
+            v => encode_bytes(buf, v.to_string().as_bytes()),
+        },
+    }
+    Ok(())
+}
+
+/// Marker separating data blocks, derived from table name and schema,
+/// so that the same input gives the same file
+fn sync_marker(name: &str, schema: &Value) -> [u8; 16] {
+    let schema = schema.to_string();
+    let mut marker = [0u8; 16];
+    for (half, chunk) in marker.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        (name, &schema, half).hash(&mut hasher);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    marker
+}
+
+/// Writes avro container file of a single table
+///
+/// The schema of a container file is fixed in its header, so rows are spooled
+/// and the file is written on close once all columns and their types are known.
+pub struct TableAvro {
+    spool: Option<RowSpool>,
+    data_path: PathBuf,
+    options: AvroOptions,
+    // Buffer reused between rows
+    cells: Vec<Option<usize>>,
+    schema: Option<TableSchema>,
+}
+
+impl TableAvro {
+    pub fn new(schema: TableSchema, data_path: PathBuf, options: AvroOptions) -> Result<TableAvro> {
+        Ok(TableAvro {
+            spool: Some(RowSpool::new(&data_path)?),
+            data_path,
+            options,
+            cells: Vec::new(),
+            schema: Some(schema),
+        })
+    }
+
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+        self.spool
+            .as_mut()
+            .unwrap()
+            .push(&row_values(schema, row, &self.cells))
+    }
+
+    fn write_block(
+        writer: &mut impl Write,
+        sync: &[u8; 16],
+        rows: usize,
+        data: &mut Vec<u8>,
+    ) -> Result<()> {
+        let mut header = Vec::new();
+        encode_long(&mut header, rows as i64);
+        encode_long(&mut header, data.len() as i64);
+        writer.write_all(&header)?;
+        writer.write_all(data)?;
+        writer.write_all(sync)?;
+        data.clear();
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let mut spool = match self.spool.take() {
+            Some(spool) => spool,
+            None => return Ok(()),
+        };
+
+        let file = File::create(&self.data_path).with_context(|| {
+            format!("Could not create file {}", self.data_path.to_string_lossy())
+        })?;
+        let mut writer = BufWriter::new(file);
+        let record_schema = avro_schema(schema);
+        let sync = sync_marker(&schema.name, &record_schema);
+
+        let mut header = Vec::from(MAGIC);
+        encode_long(&mut header, 2);
+        encode_bytes(&mut header, b"avro.schema");
+        encode_bytes(&mut header, record_schema.to_string().as_bytes());
+        encode_bytes(&mut header, b"avro.codec");
+        encode_bytes(&mut header, b"null");
+        encode_long(&mut header, 0);
+        header.extend_from_slice(&sync);
+        writer
+            .write_all(&header)
+            .context("Could not write to file")?;
+
+        let mut data = Vec::new();
+        let mut rows = 0;
+        for row in spool.read(schema.columns.len())? {
+            for (col, v) in schema.columns.iter().zip(row?.iter()) {
+                encode_value(&mut data, col, v)?;
+            }
+            rows += 1;
+            if rows >= self.options.block_size {
+                TableAvro::write_block(&mut writer, &sync, rows, &mut data)
+                    .context("Could not write to file")?;
+                rows = 0;
+            }
+        }
+        if rows > 0 {
+            TableAvro::write_block(&mut writer, &sync, rows, &mut data)
+                .context("Could not write to file")?;
+        }
+        writer.flush().context("Could not flush table")?;
+
+        spool.remove()
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Writes `.avro` container file per table with record schema derived from its columns
+pub struct DatabaseAvro {
+    schema: DatabaseSchema,
+    path: PathBuf,
+    options: AvroOptions,
+    tables: HashMap<Vec<JsonPath>, TableAvro>,
+}
+
+impl DatabaseAvro {
+    pub fn new(
+        schema: DatabaseSchema,
+        path: PathBuf,
+        options: AvroOptions,
+    ) -> Result<DatabaseAvro> {
+        let mut data_path = path.clone();
+        data_path.push("data");
+
+        ensure_dir_exists_and_empty(&data_path)?;
+
+        Ok(DatabaseAvro {
+            tables: HashMap::new(),
+            path,
+            options,
+            schema,
+        })
+    }
+
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableAvro> {
+        if !self.tables.contains_key(table_path) {
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+
+            let mut data_path = self.path.clone();
+            data_path.push("data");
+            data_path.push(table_schema.name.clone() + ".avro");
+
+            self.tables.insert(
+                table_path.to_vec(),
+                TableAvro::new(table_schema, data_path, self.options)?,
+            );
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+}
+
+impl Database for DatabaseAvro {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(&loc.table_path)?;
+        table.write(loc, record).map_err(Error::from)
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row).map_err(Error::from)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        for (table_path, table) in self.tables.iter_mut() {
+            table.close()?;
+            self.schema.return_table_schema(
+                table_path,
+                table
+                    .pop_schema()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        write_schema(&self.path, &self.schema).map_err(Error::from)
+    }
+}
@@ -1,112 +1,225 @@
 use std::collections::HashMap;
-use std::fs::{create_dir_all, File};
-use std::io::{BufWriter, Write};
+use std::fs::{remove_file, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::mem::swap;
 use std::path::PathBuf;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde_json::Value;
 
 use crate::database::{ColumnSchema, DatabaseSchema, TableSchema};
-use crate::parser::{JsonPath, TableLocation, TableRecord};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
 
+use super::output::{ensure_dir_exists_and_empty, write_schema};
 use super::Database;
 
+/// Record separator mandated by RFC 4180
+const CSV_NEWLINE: &[u8] = b"\r\n";
+
 /// Add quotes around csv string and escape them
-pub fn csv_field_quote(s: &String) -> String {
-    String::from("\"") + &s.replace("\"", "\"\"") + "\""
+pub fn csv_field_quote(s: &str) -> String {
+    String::from("\"") + &s.replace('\"', "\"\"") + "\""
+}
+
+/// Append csv field of string to `line`, escaped as by `csv_field_escape`
+fn write_field_escaped(line: &mut Vec<u8>, s: &str) {
+    if s.is_empty() || s.contains(['"', ',', '\n', '\r']) {
+        line.push(b'"');
+        for (i, part) in s.split('"').enumerate() {
+            if i > 0 {
+                line.extend_from_slice(b"\"\"");
+            }
+            line.extend_from_slice(part.as_bytes());
+        }
+        line.push(b'"');
+    } else {
+        line.extend_from_slice(s.as_bytes());
+    }
 }
 
-/// Escape double quotes and commas in string,
+/// Escape double quotes, commas and line breaks in string,
 /// making it valid csv record
-pub fn csv_field_escape(s: &String) -> String {
+pub fn csv_field_escape(s: &str) -> String {
     // Early return for empty strings
-    if s.len() == 0 {
+    if s.is_empty() {
         return String::from("\"\"");
     }
 
-    if s.contains('\"') || s.contains(',') || s.contains('\n') {
+    if s.contains('\"') || s.contains(',') || s.contains('\n') || s.contains('\r') {
         csv_field_quote(s)
     } else {
-        s.clone()
+        s.to_string()
     }
 }
 
+/// Names of table columns, in the order of the schema
+pub fn csv_header(schema: &TableSchema) -> Vec<String> {
+    schema
+        .columns
+        .iter()
+        .map(|col| col.name().to_string())
+        .collect()
+}
+
+/// Writes rows of a single table
+///
+/// Rows are spooled to a temporary file as they arrive, since the final set of columns
+/// is only known once all records are seen. On close, the spooled rows are copied
+/// into the final file under a header row, padded to the final number of columns.
 pub struct TableCsv {
     writer: BufWriter<File>,
+    spool_path: PathBuf,
+    data_path: PathBuf,
+    // Run-length encoded row widths: (number of rows, number of columns)
+    row_widths: Vec<(usize, usize)>,
+    // Buffers reused between rows
+    cells: Vec<Option<usize>>,
+    line: Vec<u8>,
     schema: Option<TableSchema>,
 }
 
 impl TableCsv {
-    pub fn new(schema: TableSchema, file: File) -> TableCsv {
-        TableCsv {
-            writer: BufWriter::new(file),
+    pub fn new(schema: TableSchema, data_path: PathBuf) -> Result<TableCsv> {
+        let mut spool_path = data_path.clone().into_os_string();
+        spool_path.push(".part");
+        let spool_path = PathBuf::from(spool_path);
+
+        let spool_file = File::create(spool_path.as_path())
+            .with_context(|| format!("Could not create file {}", spool_path.to_string_lossy()))?;
+
+        Ok(TableCsv {
+            writer: BufWriter::new(spool_file),
+            spool_path,
+            data_path,
+            row_widths: Vec::new(),
+            cells: Vec::new(),
+            line: Vec::new(),
             schema: Some(schema),
+        })
+    }
+
+    fn push_row_width(&mut self, width: usize) {
+        match self.row_widths.last_mut() {
+            Some((rows, last_width)) if *last_width == width => *rows += 1,
+            _ => self.row_widths.push((1, width)),
         }
     }
 
-    fn value_to_str(v: &serde_json::Value) -> Option<String> {
+    /// Append csv field of value to `line`, nulls are empty fields
+    fn write_value(line: &mut Vec<u8>, v: &Value) -> Result<()> {
         match v {
-            Value::Null => None,
-            Value::Bool(v) => Some(v.to_string()),
-            Value::Number(v) => Some(v.to_string()),
-            Value::String(v) => Some(csv_field_escape(v)),
+            Value::Null => {}
+            Value::Bool(v) => write!(line, "{}", v)?,
+            Value::Number(v) => write!(line, "{}", v)?,
+            Value::String(v) => write_field_escaped(line, v),
             Value::Array(_) => {
-                panic!("Arrays are not allowed in record")
+                return Err(Error::Schema(anyhow!("Arrays are not allowed in record")).into())
             }
             Value::Object(_) => {
-                panic!("Objects are not allowed in record")
+                return Err(Error::Schema(anyhow!("Objects are not allowed in record")).into())
             }
         }
+        Ok(())
     }
 
-    pub fn make_columns(&mut self, loc: TableLocation, rec: TableRecord) -> Vec<Option<String>> {
-        let schema = self.schema.as_mut().unwrap();
-        schema.update(&rec);
-        schema
-            .columns
-            .iter()
-            .map(|col| match col {
-                ColumnSchema::SourceColumn(col) => match rec.get(&col.source_path) {
-                    Some(t) => TableCsv::value_to_str(t),
-                    None => None,
-                },
-                ColumnSchema::PrimaryKey => Some(loc.object_id.to_string()),
-                ColumnSchema::ForeignKey => Some(loc.parent_object_id.to_string()),
-            })
-            .collect::<Vec<_>>()
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
     }
 
-    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
-        // Convert record to set of strings
-        let vals = self.make_columns(loc, rec);
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+
+        // Write fields into line reused between rows
+        self.line.clear();
+        for (col_id, col) in schema.columns.iter().enumerate() {
+            if col_id > 0 {
+                self.line.push(b',');
+            }
+            match col {
+                ColumnSchema::SourceColumn(_) => {
+                    if let Some((_, v)) = self.cells[col_id].and_then(|i| row.values.get_index(i)) {
+                        TableCsv::write_value(&mut self.line, v)?;
+                    }
+                }
+                ColumnSchema::PrimaryKey(_) => write!(self.line, "{}", row.object_id)?,
+                ColumnSchema::ForeignKey(_) => write!(self.line, "{}", row.parent_object_id)?,
+            }
+        }
+        self.line.extend_from_slice(CSV_NEWLINE);
+        let width = schema.columns.len();
+        self.push_row_width(width);
+
+        // Write new line to spool file from fields
+        self.writer
+            .write_all(&self.line)
+            .context("Could not write to file")
+    }
+
+    /// Copy spooled rows to the data file under a header, padding them to the header width
+    fn finalize(&mut self, header: &[String]) -> Result<()> {
+        let spool_file = File::open(self.spool_path.as_path()).with_context(|| {
+            format!("Could not open file {}", self.spool_path.to_string_lossy())
+        })?;
+        let mut spool = BufReader::new(spool_file);
+
+        let data_file = File::create(self.data_path.as_path()).with_context(|| {
+            format!("Could not create file {}", self.data_path.to_string_lossy())
+        })?;
+        let mut writer = BufWriter::new(data_file);
 
-        // Create buffered string for writing to file
-        let line = vals
+        let header_line = header
             .iter()
-            .map(|v| match v {
-                Some(v) => {
-                    // Escape separator and quotes
-                    v.clone()
-                }
-                None => String::from(""),
-            })
+            .map(|name| csv_field_escape(name))
             .collect::<Vec<_>>()
             .join(",");
-
-        let buf = line.as_bytes();
-
-        // Write new line to csv file from fields
-        self.writer.write(buf).context("Could not write to file")?;
-        self.writer
-            .write(b"\n")
+        writer
+            .write_all(header_line.as_bytes())
+            .context("Could not write to file")?;
+        writer
+            .write_all(CSV_NEWLINE)
             .context("Could not write to file")?;
 
-        Ok(())
+        let mut row = Vec::<u8>::new();
+        for (rows, width) in self.row_widths.iter() {
+            let padding = ",".repeat(header.len() - width);
+            for _ in 0..*rows {
+                if !read_csv_row(&mut spool, &mut row).context("Could not read spooled rows")? {
+                    bail!("Spooled rows ended unexpectedly");
+                }
+                writer.write_all(&row).context("Could not write to file")?;
+                writer
+                    .write_all(padding.as_bytes())
+                    .context("Could not write to file")?;
+                writer
+                    .write_all(CSV_NEWLINE)
+                    .context("Could not write to file")?;
+            }
+        }
+
+        writer.flush().context("Could not flush table")
     }
 
     pub fn close(&mut self) -> Result<()> {
-        self.writer.flush().context("Could not flush table")
+        self.writer.flush().context("Could not flush table")?;
+
+        let header = csv_header(
+            self.schema
+                .as_ref()
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+        );
+        self.finalize(&header)?;
+
+        remove_file(self.spool_path.as_path()).with_context(|| {
+            format!(
+                "Could not remove file {}",
+                self.spool_path.to_string_lossy()
+            )
+        })
     }
 
     pub fn pop_schema(&mut self) -> Option<TableSchema> {
@@ -116,27 +229,26 @@ impl TableCsv {
     }
 }
 
-fn ensure_dir_exists_and_empty(path: &PathBuf) -> Result<()> {
-    create_dir_all(path).with_context(|| {
-        format!(
-            "Could not ensure directory {} exists",
-            path.to_string_lossy().to_string()
-        )
-    })?;
-    let is_empty = path
-        .read_dir()
-        .with_context(|| {
-            format!(
-                "Could not read directory {} to check if it is empty",
-                path.to_string_lossy()
-            )
-        })?
-        .next()
-        .is_none();
-    if is_empty {
-        Ok(())
-    } else {
-        Err(anyhow!("Directory {} is not empty", path.to_string_lossy()))
+/// Read single csv row without the trailing line break into `row`
+///
+/// Line breaks inside of quoted fields are kept as part of the row.
+/// Returns false if reader is exhausted
+fn read_csv_row<B: BufRead>(reader: &mut B, row: &mut Vec<u8>) -> Result<bool> {
+    row.clear();
+    let mut quotes: usize = 0;
+    loop {
+        let start = row.len();
+        if reader.read_until(b'\n', row)? == 0 {
+            return Ok(!row.is_empty());
+        }
+        // Row ends at a line break outside of quotes
+        quotes += row[start..].iter().filter(|b| **b == b'"').count();
+        if quotes.is_multiple_of(2) {
+            if row.ends_with(CSV_NEWLINE) {
+                row.truncate(row.len() - CSV_NEWLINE.len());
+            }
+            return Ok(true);
+        }
     }
 }
 
@@ -160,11 +272,14 @@ impl DatabaseCsv {
         })
     }
 
-    fn get_or_create_table_mut(&mut self, table_path: &Vec<JsonPath>) -> Result<&mut TableCsv> {
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableCsv> {
         if !self.tables.contains_key(table_path) {
             // Table schema can only be poped once, transferring ownership of the schema to the table
             // Consequent calls to pop table_schema for same table path should panic
-            let table_schema = self.schema.borrow_table_schema(table_path).unwrap();
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
             let table_name = &table_schema.name;
 
             let data_filename = table_name.clone() + ".csv";
@@ -173,10 +288,8 @@ impl DatabaseCsv {
             data_path.push("data");
             data_path.push(data_filename);
 
-            let data_file = File::create(data_path.as_path()).unwrap();
-
             self.tables
-                .insert(table_path.clone(), TableCsv::new(table_schema, data_file));
+                .insert(table_path.to_vec(), TableCsv::new(table_schema, data_path)?);
         }
         Ok(self.tables.get_mut(table_path).unwrap())
     }
@@ -191,30 +304,28 @@ impl Database for DatabaseCsv {
         &mut self.schema
     }
 
-    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<()> {
-        let table = self.get_or_create_table_mut(&loc.table_path)?;
-        table.write(loc, record)?;
-        Ok(())
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        self.write_row(&Row::from_record(&loc, &record))
     }
 
-    fn close(&mut self) -> Result<()> {
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row).map_err(Error::from)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
         for (table_path, table) in self.tables.iter_mut() {
             table.close()?;
             self.schema.return_table_schema(
                 table_path,
                 table
                     .pop_schema()
-                    .expect("Tried return non-existent schema"),
-            );
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
         }
 
-        self.schema.ensure_all_tables_returned();
+        self.schema.ensure_all_tables_returned()?;
 
-        // Save schema o file
-        let mut schema_filename = self.path.clone();
-        schema_filename.push("schema.json");
-        let schema_file = File::create(schema_filename.as_path())?;
-        serde_json::to_writer_pretty(BufWriter::new(schema_file), &self.schema)?;
-        Ok(())
+        write_schema(&self.path, &self.schema).map_err(Error::from)
     }
 }
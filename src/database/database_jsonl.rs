@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::swap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::database::{ColumnSchema, DatabaseSchema, TableSchema};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{ensure_dir_exists_and_empty, write_schema};
+use super::Database;
+
+/// Writes records of a single table as json objects, one per line
+///
+/// Keys follow the column order of the schema, columns missing from a record are omitted.
+pub struct TableJsonl {
+    writer: BufWriter<File>,
+    // Buffers reused between rows
+    cells: Vec<Option<usize>>,
+    line: Vec<u8>,
+    schema: Option<TableSchema>,
+}
+
+impl TableJsonl {
+    pub fn new(schema: TableSchema, data_path: PathBuf) -> Result<TableJsonl> {
+        let file = File::create(data_path.as_path())
+            .with_context(|| format!("Could not create file {}", data_path.to_string_lossy()))?;
+        Ok(TableJsonl {
+            writer: BufWriter::new(file),
+            cells: Vec::new(),
+            line: Vec::new(),
+            schema: Some(schema),
+        })
+    }
+
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+
+        let line = &mut self.line;
+        line.clear();
+        line.push(b'{');
+        for (col_id, col) in schema.columns.iter().enumerate() {
+            if let ColumnSchema::SourceColumn(_) = col {
+                if self.cells[col_id].is_none() {
+                    continue;
+                }
+            }
+            if line.len() > 1 {
+                line.push(b',');
+            }
+            serde_json::to_writer(&mut *line, col.name())?;
+            line.push(b':');
+            match col {
+                ColumnSchema::SourceColumn(_) => {
+                    if let Some((_, value)) =
+                        self.cells[col_id].and_then(|i| row.values.get_index(i))
+                    {
+                        serde_json::to_writer(&mut *line, value)?;
+                    }
+                }
+                ColumnSchema::PrimaryKey(_) => write!(line, "{}", row.object_id)?,
+                ColumnSchema::ForeignKey(_) => write!(line, "{}", row.parent_object_id)?,
+            }
+        }
+        line.extend(b"}\n");
+
+        self.writer
+            .write_all(line)
+            .context("Could not write to file")
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.writer.flush().context("Could not flush table")
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Writes `.jsonl` file per table, keeping json types of values
+pub struct DatabaseJsonl {
+    schema: DatabaseSchema,
+    path: PathBuf,
+    tables: HashMap<Vec<JsonPath>, TableJsonl>,
+}
+
+impl DatabaseJsonl {
+    pub fn new(schema: DatabaseSchema, path: PathBuf) -> Result<DatabaseJsonl> {
+        let mut data_path = path.clone();
+        data_path.push("data");
+
+        ensure_dir_exists_and_empty(&data_path)?;
+
+        Ok(DatabaseJsonl {
+            tables: HashMap::new(),
+            path,
+            schema,
+        })
+    }
+
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableJsonl> {
+        if !self.tables.contains_key(table_path) {
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+
+            let mut data_path = self.path.clone();
+            data_path.push("data");
+            data_path.push(table_schema.name.clone() + ".jsonl");
+
+            self.tables.insert(
+                table_path.to_vec(),
+                TableJsonl::new(table_schema, data_path)?,
+            );
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+}
+
+impl Database for DatabaseJsonl {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        self.write_row(&Row::from_record(&loc, &record))
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row).map_err(Error::from)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        for (table_path, table) in self.tables.iter_mut() {
+            table.close()?;
+            self.schema.return_table_schema(
+                table_path,
+                table
+                    .pop_schema()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        write_schema(&self.path, &self.schema).map_err(Error::from)
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::mem::swap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use parquet::basic::{Compression, LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use parquet::schema::types::Type;
+use serde_json::Value;
+
+use crate::database::{ColumnSchema, DatabaseSchema, TableSchema, ValueType};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{ensure_dir_exists_and_empty, row_values, write_schema, RowSpool};
+use super::Database;
+
+/// Number of rows in row group unless configured otherwise
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy)]
+pub struct ParquetOptions {
+    /// Maximum number of rows in row group, bounds memory used on close
+    pub row_group_size: usize,
+    pub compression: Compression,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions {
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+            compression: Compression::SNAPPY,
+        }
+    }
+}
+
+/// Type of column in parquet file
+fn column_type(col: &ColumnSchema) -> Result<Type> {
+    let (physical_type, logical_type, repetition) = match col {
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => {
+            (PhysicalType::INT32, None, Repetition::REQUIRED)
+        }
+        ColumnSchema::SourceColumn(col) => match col.value_type() {
+            ValueType::Bool => (PhysicalType::BOOLEAN, None, Repetition::OPTIONAL),
+            ValueType::Integer => (PhysicalType::INT64, None, Repetition::OPTIONAL),
+            ValueType::Float => (PhysicalType::DOUBLE, None, Repetition::OPTIONAL),
+            ValueType::Null | ValueType::String => (
+                PhysicalType::BYTE_ARRAY,
+                Some(LogicalType::String),
+                Repetition::OPTIONAL,
+            ),
+        },
+    };
+    Ok(Type::primitive_type_builder(col.name(), physical_type)
+        .with_logical_type(logical_type)
+        .with_repetition(repetition)
+        .build()?)
+}
+
+/// Render value of string column, values of other types keep their json form
+fn value_to_string(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+/// Write values of column `col_id` of `rows`
+fn write_column(
+    writer: &mut SerializedColumnWriter<'_>,
+    col: &ColumnSchema,
+    col_id: usize,
+    rows: &[Vec<Value>],
+) -> Result<()> {
+    let values = rows.iter().map(|row| &row[col_id]);
+    // Definition level 0 marks null in optional column
+    let def_levels = values
+        .clone()
+        .map(|v| i16::from(!v.is_null()))
+        .collect::<Vec<_>>();
+    let present = values.filter(|v| !v.is_null());
+
+    let value_type = match col {
+        ColumnSchema::SourceColumn(col) => col.value_type(),
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => {
+            let ids = present
+                .map(|v| v.as_i64().unwrap_or_default() as i32)
+                .collect::<Vec<_>>();
+            writer.typed::<Int32Type>().write_batch(&ids, None, None)?;
+            return Ok(());
+        }
+    };
+    match value_type {
+        ValueType::Bool => {
+            let values = present
+                .map(|v| v.as_bool().unwrap_or_default())
+                .collect::<Vec<_>>();
+            writer
+                .typed::<BoolType>()
+                .write_batch(&values, Some(&def_levels), None)?;
+        }
+        ValueType::Integer => {
+            let values = present
+                .map(|v| v.as_i64().unwrap_or_default())
+                .collect::<Vec<_>>();
+            writer
+                .typed::<Int64Type>()
+                .write_batch(&values, Some(&def_levels), None)?;
+        }
+        ValueType::Float => {
+            let values = present
+                .map(|v| v.as_f64().unwrap_or_default())
+                .collect::<Vec<_>>();
+            writer
+                .typed::<DoubleType>()
+                .write_batch(&values, Some(&def_levels), None)?;
+        }
+        ValueType::Null | ValueType::String => {
+            let values = present
+                .map(|v| ByteArray::from(value_to_string(v).into_bytes()))
+                .collect::<Vec<_>>();
+            writer
+                .typed::<ByteArrayType>()
+                .write_batch(&values, Some(&def_levels), None)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes parquet file of a single table
+///
+/// Column types are only known once all records are seen, so rows are spooled
+/// and the file is written on close, one row group at a time.
+pub struct TableParquet {
+    spool: Option<RowSpool>,
+    data_path: PathBuf,
+    options: ParquetOptions,
+    // Buffer reused between rows
+    cells: Vec<Option<usize>>,
+    schema: Option<TableSchema>,
+}
+
+impl TableParquet {
+    pub fn new(
+        schema: TableSchema,
+        data_path: PathBuf,
+        options: ParquetOptions,
+    ) -> Result<TableParquet> {
+        Ok(TableParquet {
+            spool: Some(RowSpool::new(&data_path)?),
+            data_path,
+            options,
+            cells: Vec::new(),
+            schema: Some(schema),
+        })
+    }
+
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+        self.spool
+            .as_mut()
+            .unwrap()
+            .push(&row_values(schema, row, &self.cells))
+    }
+
+    fn write_row_group(
+        writer: &mut SerializedFileWriter<File>,
+        schema: &TableSchema,
+        rows: &[Vec<Value>],
+    ) -> Result<()> {
+        let mut row_group = writer.next_row_group()?;
+        let mut col_id = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            write_column(&mut column, &schema.columns[col_id], col_id, rows)?;
+            column.close()?;
+            col_id += 1;
+        }
+        row_group.close()?;
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let mut spool = match self.spool.take() {
+            Some(spool) => spool,
+            None => return Ok(()),
+        };
+
+        let fields = schema
+            .columns
+            .iter()
+            .map(|col| column_type(col).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        let file_schema = Type::group_type_builder(&schema.name)
+            .with_fields(fields)
+            .build()?;
+        let properties = WriterProperties::builder()
+            .set_compression(self.options.compression)
+            .set_max_row_group_size(self.options.row_group_size)
+            .build();
+
+        let file = File::create(&self.data_path).with_context(|| {
+            format!("Could not create file {}", self.data_path.to_string_lossy())
+        })?;
+        let mut writer =
+            SerializedFileWriter::new(file, Arc::new(file_schema), Arc::new(properties))?;
+
+        let mut rows = Vec::with_capacity(self.options.row_group_size.min(spool.rows()));
+        for row in spool.read(schema.columns.len())? {
+            rows.push(row?);
+            if rows.len() >= self.options.row_group_size {
+                TableParquet::write_row_group(&mut writer, schema, &rows)?;
+                rows.clear();
+            }
+        }
+        if !rows.is_empty() {
+            TableParquet::write_row_group(&mut writer, schema, &rows)?;
+        }
+        writer.close()?;
+
+        spool.remove()
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Writes `.parquet` file per table with column types inferred from values
+pub struct DatabaseParquet {
+    schema: DatabaseSchema,
+    path: PathBuf,
+    options: ParquetOptions,
+    tables: HashMap<Vec<JsonPath>, TableParquet>,
+}
+
+impl DatabaseParquet {
+    pub fn new(
+        schema: DatabaseSchema,
+        path: PathBuf,
+        options: ParquetOptions,
+    ) -> Result<DatabaseParquet> {
+        let mut data_path = path.clone();
+        data_path.push("data");
+
+        ensure_dir_exists_and_empty(&data_path)?;
+
+        Ok(DatabaseParquet {
+            tables: HashMap::new(),
+            path,
+            options,
+            schema,
+        })
+    }
+
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableParquet> {
+        if !self.tables.contains_key(table_path) {
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+
+            let mut data_path = self.path.clone();
+            data_path.push("data");
+            data_path.push(table_schema.name.clone() + ".parquet");
+
+            self.tables.insert(
+                table_path.to_vec(),
+                TableParquet::new(table_schema, data_path, self.options)?,
+            );
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+}
+
+impl Database for DatabaseParquet {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(&loc.table_path)?;
+        table.write(loc, record).map_err(Error::from)
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row).map_err(Error::from)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        for (table_path, table) in self.tables.iter_mut() {
+            table.close()?;
+            self.schema.return_table_schema(
+                table_path,
+                table
+                    .pop_schema()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        write_schema(&self.path, &self.schema).map_err(Error::from)
+    }
+}
@@ -0,0 +1,387 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::mem::swap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use postgres::{Client, NoTls};
+use serde_json::Value;
+
+use crate::database::database_csv::{csv_field_escape, csv_field_quote};
+use crate::database::ddl::{column_type, quote_identifier};
+use crate::database::{ColumnSchema, DatabaseSchema, Dialect, TableSchema, ValueType};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{row_values, write_schema};
+use super::Database;
+
+/// Number of rows buffered per table before copying them unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+#[derive(Clone)]
+pub struct PostgresOptions {
+    /// Connection string, e.g. `postgresql://user@localhost/db`
+    pub url: String,
+    /// Postgres schema to create tables in instead of the default one
+    pub schema_name: Option<String>,
+    /// Number of rows buffered per table before copying them
+    pub batch_size: usize,
+}
+
+impl PostgresOptions {
+    pub fn new(url: String) -> PostgresOptions {
+        PostgresOptions {
+            url,
+            schema_name: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+fn q(name: &str) -> String {
+    quote_identifier(Dialect::Postgres, name)
+}
+
+/// Type of column in postgres, keys are integers
+fn pg_type(col: &ColumnSchema) -> &'static str {
+    match col {
+        ColumnSchema::SourceColumn(col) => column_type(Dialect::Postgres, col.value_type()),
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => {
+            column_type(Dialect::Postgres, ValueType::Integer)
+        }
+    }
+}
+
+/// Type of column while loading, source columns hold values as copied until `declare_types`
+fn load_type(col: &ColumnSchema) -> &'static str {
+    match col {
+        ColumnSchema::SourceColumn(_) => column_type(Dialect::Postgres, ValueType::String),
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => pg_type(col),
+    }
+}
+
+/// Value in csv format of COPY, where unquoted empty field is null
+fn copy_field(v: &Value) -> String {
+    match v {
+        Value::Null => String::new(),
+        // Unquoted `\.` line marks end of data
+        Value::String(s) if s == "\\." => csv_field_quote(s),
+        Value::String(s) => csv_field_escape(s),
+        v => csv_field_escape(&v.to_string()),
+    }
+}
+
+/// Rows of a single table waiting to be copied
+pub struct TablePostgres {
+    // Qualified and quoted table name
+    table_name: String,
+    // Number of schema columns that exist in database table
+    created_columns: usize,
+    rows: Vec<Vec<Value>>,
+    // Buffer reused between rows
+    cells: Vec<Option<usize>>,
+    schema: Option<TableSchema>,
+}
+
+impl TablePostgres {
+    pub fn new(schema: TableSchema, schema_name: Option<&str>) -> TablePostgres {
+        let table_name = match schema_name {
+            Some(schema_name) => q(schema_name) + "." + &q(&schema.name),
+            None => q(&schema.name),
+        };
+        TablePostgres {
+            table_name,
+            created_columns: 0,
+            rows: Vec::new(),
+            cells: Vec::new(),
+            schema: Some(schema),
+        }
+    }
+
+    /// Create table and add new columns
+    fn sync_columns(&mut self, client: &mut Client) -> Result<()> {
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let mut statements = Vec::new();
+
+        if self.created_columns == 0 {
+            let columns = schema
+                .columns
+                .iter()
+                .map(|col| format!("{} {}", q(col.name()), load_type(col)))
+                .collect::<Vec<_>>();
+            statements.push(format!(
+                "CREATE TABLE {} ({})",
+                self.table_name,
+                columns.join(", ")
+            ));
+        } else {
+            for col in schema.columns[self.created_columns..].iter() {
+                statements.push(format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    self.table_name,
+                    q(col.name()),
+                    load_type(col)
+                ));
+            }
+        }
+
+        for statement in statements {
+            client
+                .batch_execute(&statement)
+                .with_context(|| format!("Could not execute {}", statement))?;
+        }
+        self.created_columns = schema.columns.len();
+        Ok(())
+    }
+
+    /// Cast columns to the type fitting all their values, once all rows are copied
+    ///
+    /// Widening a typed column would make postgres render values copied before,
+    /// e.g. `1.0` as `1`, so values are kept as their source text until then.
+    fn declare_types(&self, client: &mut Client) -> Result<()> {
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let changes = schema
+            .columns
+            .iter()
+            .filter(|col| load_type(col) != pg_type(col))
+            .map(|col| {
+                format!(
+                    "ALTER COLUMN {} TYPE {} USING {}::{}",
+                    q(col.name()),
+                    pg_type(col),
+                    q(col.name()),
+                    pg_type(col)
+                )
+            })
+            .collect::<Vec<_>>();
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let statement = format!("ALTER TABLE {} {}", self.table_name, changes.join(", "));
+        client
+            .batch_execute(&statement)
+            .with_context(|| format!("Could not execute {}", statement))
+    }
+
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+        let values = row_values(schema, row, &self.cells);
+        self.rows
+            .push(values.into_iter().map(Cow::into_owned).collect());
+        Ok(())
+    }
+
+    /// Copy buffered rows to database
+    pub fn flush(&mut self, client: &mut Client) -> Result<()> {
+        self.sync_columns(client)?;
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let columns = schema
+            .columns
+            .iter()
+            .map(|col| q(col.name()))
+            .collect::<Vec<_>>();
+        let mut data = Vec::<u8>::new();
+        for row in self.rows.drain(..) {
+            let mut fields = row.iter().map(copy_field).collect::<Vec<_>>();
+            fields.resize(columns.len(), String::new());
+            data.extend(fields.join(",").as_bytes());
+            data.push(b'\n');
+        }
+
+        let mut writer = client
+            .copy_in(&format!(
+                "COPY {} ({}) FROM STDIN WITH (FORMAT csv)",
+                self.table_name,
+                columns.join(", ")
+            ))
+            .context("Could not start copying")?;
+        writer.write_all(&data).context("Could not copy rows")?;
+        writer.finish().context("Could not copy rows")?;
+        Ok(())
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Loads tables directly into postgres through COPY
+///
+/// Tables are created on first flush and altered as new columns appear, column
+/// types are declared at close.
+/// Everything is loaded in a single transaction, foreign keys are added
+/// at close, once parent rows are loaded. Only `schema.json` is written to `path`.
+pub struct DatabasePostgres {
+    schema: DatabaseSchema,
+    path: PathBuf,
+    options: PostgresOptions,
+    client: Client,
+    tables: HashMap<Vec<JsonPath>, TablePostgres>,
+}
+
+impl DatabasePostgres {
+    pub fn new(
+        mut schema: DatabaseSchema,
+        path: PathBuf,
+        options: PostgresOptions,
+    ) -> Result<DatabasePostgres> {
+        let mut client =
+            Client::connect(&options.url, NoTls).context("Could not connect to postgres")?;
+        client.batch_execute("BEGIN")?;
+
+        if schema.dialect() == Dialect::Generic {
+            schema.set_dialect(Dialect::Postgres);
+        }
+
+        Ok(DatabasePostgres {
+            schema,
+            path,
+            options,
+            client,
+            tables: HashMap::new(),
+        })
+    }
+
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TablePostgres> {
+        if !self.tables.contains_key(table_path) {
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+            self.tables.insert(
+                table_path.to_vec(),
+                TablePostgres::new(table_schema, self.options.schema_name.as_deref()),
+            );
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+
+    /// Statements adding primary key and foreign key to parent table
+    fn key_constraints(&self) -> Result<Vec<String>> {
+        let mut statements = Vec::new();
+        for (table_path, table) in self.tables.iter() {
+            let schema = table
+                .schema
+                .as_ref()
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+            if let Some(key) = schema.primary_key() {
+                statements.push(format!(
+                    "ALTER TABLE {} ADD PRIMARY KEY ({})",
+                    table.table_name,
+                    q(&key.name)
+                ));
+            }
+            let parent = table_path
+                .split_last()
+                .and_then(|(_, parent_path)| self.tables.get(parent_path));
+            if let (Some(key), Some(parent)) = (schema.foreign_key(), parent) {
+                let parent_key = parent
+                    .schema
+                    .as_ref()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?
+                    .primary_key()
+                    .ok_or_else(|| {
+                        Error::Schema(anyhow!(
+                            "Parent table {} has no primary key",
+                            parent.table_name
+                        ))
+                    })?;
+                statements.push(format!(
+                    "ALTER TABLE {} ADD FOREIGN KEY ({}) REFERENCES {} ({})",
+                    table.table_name,
+                    q(&key.name),
+                    parent.table_name,
+                    q(&parent_key.name)
+                ));
+            }
+        }
+        // Primary keys must exist before foreign keys refer to them
+        statements.sort_by_key(|statement| statement.contains("FOREIGN KEY"));
+        Ok(statements)
+    }
+}
+
+impl Database for DatabasePostgres {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        self.write_row(&Row::from_record(&loc, &record))
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row)?;
+
+        let table = self.tables.get_mut(row.table_path).unwrap();
+        if table.rows.len() >= self.options.batch_size {
+            table.flush(&mut self.client)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        for table in self.tables.values_mut() {
+            table.flush(&mut self.client)?;
+            table.declare_types(&mut self.client)?;
+        }
+        for statement in self.key_constraints()? {
+            self.client
+                .batch_execute(&statement)
+                .with_context(|| format!("Could not execute {}", statement))?;
+        }
+        self.client
+            .batch_execute("COMMIT")
+            .context("Could not execute COMMIT")?;
+
+        for (table_path, table) in self.tables.iter_mut() {
+            self.schema.return_table_schema(
+                table_path,
+                table
+                    .pop_schema()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        std::fs::create_dir_all(&self.path).with_context(|| {
+            format!(
+                "Could not ensure directory {} exists",
+                self.path.to_string_lossy()
+            )
+        })?;
+        write_schema(&self.path, &self.schema).map_err(Error::from)
+    }
+}
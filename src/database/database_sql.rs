@@ -0,0 +1,273 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{remove_dir, remove_file, File};
+use std::io::{copy, BufWriter, Write};
+use std::mem::swap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+use crate::database::ddl::{quote_identifier, quote_string, tables_parents_first};
+use crate::database::{DatabaseSchema, Dialect, TableSchema};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{ensure_dir_exists_and_empty, row_values, write_schema};
+use super::Database;
+
+/// Number of rows in single `INSERT` statement unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Name of combined file within output directory
+pub const COMBINED_FILE_NAME: &str = "data.sql";
+
+#[derive(Clone, Copy)]
+pub struct SqlOptions {
+    /// Number of rows in single `INSERT` statement
+    pub batch_size: usize,
+    /// Write all tables into single file, parents before children
+    pub combined: bool,
+}
+
+impl Default for SqlOptions {
+    fn default() -> Self {
+        SqlOptions {
+            batch_size: DEFAULT_BATCH_SIZE,
+            combined: false,
+        }
+    }
+}
+
+/// Sql literal of value
+pub fn sql_literal(dialect: Dialect, v: &Value) -> String {
+    match v {
+        Value::Null => String::from("NULL"),
+        Value::Bool(v) => match (dialect, v) {
+            (Dialect::Sqlite, true) => String::from("1"),
+            (Dialect::Sqlite, false) => String::from("0"),
+            (_, true) => String::from("TRUE"),
+            (_, false) => String::from("FALSE"),
+        },
+        Value::Number(v) => v.to_string(),
+        Value::String(v) => quote_string(dialect, v),
+        v => quote_string(dialect, &v.to_string()),
+    }
+}
+
+/// Writes `INSERT` statements of a single table
+pub struct TableSql {
+    writer: BufWriter<File>,
+    data_path: PathBuf,
+    dialect: Dialect,
+    batch_size: usize,
+    rows: Vec<Vec<Value>>,
+    // Buffer reused between rows
+    cells: Vec<Option<usize>>,
+    schema: Option<TableSchema>,
+}
+
+impl TableSql {
+    pub fn new(
+        schema: TableSchema,
+        data_path: PathBuf,
+        dialect: Dialect,
+        batch_size: usize,
+    ) -> Result<TableSql> {
+        let file = File::create(data_path.as_path())
+            .with_context(|| format!("Could not create file {}", data_path.to_string_lossy()))?;
+        Ok(TableSql {
+            writer: BufWriter::new(file),
+            data_path,
+            dialect,
+            batch_size,
+            rows: Vec::new(),
+            cells: Vec::new(),
+            schema: Some(schema),
+        })
+    }
+
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+        let values = row_values(schema, row, &self.cells);
+        self.rows
+            .push(values.into_iter().map(Cow::into_owned).collect());
+        if self.rows.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write buffered rows as single statement, with columns known so far
+    fn flush(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let dialect = self.dialect;
+        let columns = schema
+            .columns
+            .iter()
+            .map(|col| quote_identifier(dialect, col.name()))
+            .collect::<Vec<_>>();
+
+        let mut statement = format!(
+            "INSERT INTO {} ({}) VALUES\n",
+            quote_identifier(dialect, &schema.name),
+            columns.join(", ")
+        );
+        for (row_id, row) in self.rows.drain(..).enumerate() {
+            let mut values = row
+                .iter()
+                .map(|v| sql_literal(dialect, v))
+                .collect::<Vec<_>>();
+            values.resize(columns.len(), String::from("NULL"));
+            if row_id > 0 {
+                statement += ",\n";
+            }
+            statement += &format!("({})", values.join(", "));
+        }
+        statement += ";\n";
+
+        self.writer
+            .write_all(statement.as_bytes())
+            .context("Could not write to file")
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.flush()?;
+        self.writer.flush().context("Could not flush table")
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Writes `.sql` file of `INSERT` statements per table, quoted for target dialect of the schema
+///
+/// With `combined` option the files are joined into single file at close,
+/// ordered so that parent rows are inserted before their children.
+pub struct DatabaseSql {
+    schema: DatabaseSchema,
+    path: PathBuf,
+    options: SqlOptions,
+    tables: HashMap<Vec<JsonPath>, TableSql>,
+}
+
+impl DatabaseSql {
+    pub fn new(schema: DatabaseSchema, path: PathBuf, options: SqlOptions) -> Result<DatabaseSql> {
+        let mut data_path = path.clone();
+        data_path.push("data");
+
+        ensure_dir_exists_and_empty(&data_path)?;
+
+        Ok(DatabaseSql {
+            tables: HashMap::new(),
+            path,
+            options,
+            schema,
+        })
+    }
+
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableSql> {
+        if !self.tables.contains_key(table_path) {
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+
+            let mut data_path = self.path.clone();
+            data_path.push("data");
+            data_path.push(table_schema.name.clone() + ".sql");
+
+            let table = TableSql::new(
+                table_schema,
+                data_path,
+                self.schema.dialect(),
+                self.options.batch_size,
+            )?;
+            self.tables.insert(table_path.to_vec(), table);
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+
+    /// Join table files into single file, parents first
+    fn combine(&mut self) -> Result<()> {
+        let combined_path = self.path.join(COMBINED_FILE_NAME);
+        let file = File::create(&combined_path).with_context(|| {
+            format!("Could not create file {}", combined_path.to_string_lossy())
+        })?;
+        let mut writer = BufWriter::new(file);
+        for table in tables_parents_first(&self.schema) {
+            let data_path = match self.tables.get(&table.path) {
+                Some(table) => table.data_path.clone(),
+                None => continue,
+            };
+            let mut reader = File::open(&data_path)
+                .with_context(|| format!("Could not read file {}", data_path.to_string_lossy()))?;
+            copy(&mut reader, &mut writer).context("Could not write to file")?;
+            remove_file(&data_path).with_context(|| {
+                format!("Could not remove file {}", data_path.to_string_lossy())
+            })?;
+        }
+        writer.flush().context("Could not flush file")?;
+
+        let data_path = self.path.join("data");
+        remove_dir(&data_path)
+            .with_context(|| format!("Could not remove directory {}", data_path.to_string_lossy()))
+    }
+}
+
+impl Database for DatabaseSql {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(&loc.table_path)?;
+        table.write(loc, record).map_err(Error::from)
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row).map_err(Error::from)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        for (table_path, table) in self.tables.iter_mut() {
+            table.close()?;
+            self.schema.return_table_schema(
+                table_path,
+                table
+                    .pop_schema()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        if self.options.combined {
+            self.combine()?;
+        }
+
+        write_schema(&self.path, &self.schema).map_err(Error::from)
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::mem::swap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use rusqlite::types::{ToSqlOutput, Value as SqlValue, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use serde_json::Value;
+
+use crate::database::{ColumnSchema, DatabaseSchema, Dialect, TableSchema, ValueType};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{ensure_dir_exists_and_empty, row_values, write_schema};
+use super::Database;
+
+/// Name of database file unless configured otherwise
+pub const DEFAULT_FILE_NAME: &str = "database.sqlite";
+
+/// Number of rows inserted in single transaction unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+#[derive(Clone)]
+pub struct SqliteOptions {
+    /// Name of database file within output directory
+    pub file_name: String,
+    /// Number of rows inserted in single transaction
+    pub batch_size: usize,
+}
+
+impl Default for SqliteOptions {
+    fn default() -> Self {
+        SqliteOptions {
+            file_name: String::from(DEFAULT_FILE_NAME),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    String::from("\"") + &name.replace('"', "\"\"") + "\""
+}
+
+/// Type affinity of source column, none while it only holds nulls
+fn column_type(col: &ColumnSchema) -> &'static str {
+    match col {
+        ColumnSchema::SourceColumn(col) => match col.value_type() {
+            ValueType::Null => "",
+            ValueType::Bool | ValueType::Integer => " INTEGER",
+            ValueType::Float => " REAL",
+            ValueType::String => " TEXT",
+        },
+        ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => "",
+    }
+}
+
+/// Column definition for `CREATE TABLE` and `ALTER TABLE`
+///
+/// Source columns get type affinity of their value type.
+/// Foreign key refers to `parent` given as table name and its key column name
+fn column_definition(parent: Option<(&str, &str)>, col: &ColumnSchema) -> String {
+    let name = quote_identifier(col.name());
+    match col {
+        ColumnSchema::PrimaryKey(_) => name + " INTEGER PRIMARY KEY",
+        ColumnSchema::ForeignKey(_) => match parent {
+            Some((parent, parent_key)) => format!(
+                "{} INTEGER NOT NULL REFERENCES {}({})",
+                name,
+                quote_identifier(parent),
+                quote_identifier(parent_key)
+            ),
+            None => name + " INTEGER NOT NULL",
+        },
+        ColumnSchema::SourceColumn(_) => name + column_type(col),
+    }
+}
+
+/// Sql value of json value, strings are bound without copying
+fn to_sql_value(v: &Value) -> ToSqlOutput<'_> {
+    let v = match v {
+        Value::Null => SqlValue::Null,
+        Value::Bool(v) => SqlValue::Integer(i64::from(*v)),
+        Value::Number(v) => match (v.as_i64(), v.as_f64()) {
+            (Some(v), _) => SqlValue::Integer(v),
+            (None, Some(v)) => SqlValue::Real(v),
+            (None, None) => SqlValue::Text(v.to_string()),
+        },
+        Value::String(v) => return ToSqlOutput::Borrowed(ValueRef::Text(v.as_bytes())),
+        v => SqlValue::Text(v.to_string()),
+    };
+    ToSqlOutput::Owned(v)
+}
+
+/// State of a single table in database file
+pub struct TableSqlite {
+    // Names of table and its key column referred to by foreign key
+    parent: Option<(String, String)>,
+    // Types of schema columns that exist in database table
+    declared_types: Vec<&'static str>,
+    insert_sql: String,
+    // Buffer reused between rows
+    cells: Vec<Option<usize>>,
+    schema: Option<TableSchema>,
+}
+
+impl TableSqlite {
+    pub fn new(schema: TableSchema, parent: Option<(String, String)>) -> TableSqlite {
+        TableSqlite {
+            parent,
+            declared_types: Vec::new(),
+            insert_sql: String::new(),
+            cells: Vec::new(),
+            schema: Some(schema),
+        }
+    }
+
+    /// Create table, add columns that appeared since last call, or recreate it
+    /// if types of its columns changed
+    ///
+    /// Sqlite converts values to the type declared for their column, e.g. `"012"`
+    /// to `12`, so table is recreated before inserting values of another type.
+    /// Column types only widen, so this happens a few times per column at most.
+    fn sync_columns(&mut self, conn: &Connection) -> Result<()> {
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let parent = self
+            .parent
+            .as_ref()
+            .map(|(name, key)| (name.as_str(), key.as_str()));
+        let types_changed = schema
+            .columns
+            .iter()
+            .zip(self.declared_types.iter())
+            .any(|(col, declared_type)| column_type(col) != *declared_type);
+        if !types_changed && self.declared_types.len() == schema.columns.len() {
+            return Ok(());
+        }
+
+        let table_name = quote_identifier(&schema.name);
+        if self.declared_types.is_empty() {
+            let columns = schema
+                .columns
+                .iter()
+                .map(|col| column_definition(parent, col))
+                .collect::<Vec<_>>();
+            conn.execute(
+                &format!("CREATE TABLE {} ({})", table_name, columns.join(", ")),
+                [],
+            )
+            .with_context(|| format!("Could not create table {}", schema.name))?;
+        } else if types_changed {
+            self.recreate(conn, schema, parent)?;
+        } else {
+            for col in schema.columns[self.declared_types.len()..].iter() {
+                conn.execute(
+                    &format!(
+                        "ALTER TABLE {} ADD COLUMN {}",
+                        table_name,
+                        column_definition(parent, col)
+                    ),
+                    [],
+                )
+                .with_context(|| format!("Could not add column {}", col.name()))?;
+            }
+        }
+        self.declared_types = schema.columns.iter().map(column_type).collect();
+
+        let names = schema
+            .columns
+            .iter()
+            .map(|col| quote_identifier(col.name()))
+            .collect::<Vec<_>>();
+        let placeholders = vec!["?"; names.len()];
+        self.insert_sql = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            table_name,
+            names.join(", "),
+            placeholders.join(", ")
+        );
+        Ok(())
+    }
+
+    /// Copy rows into new table with all columns of `schema` and their current types
+    fn recreate(
+        &self,
+        conn: &Connection,
+        schema: &TableSchema,
+        parent: Option<(&str, &str)>,
+    ) -> Result<()> {
+        let mut new_name = schema.name.clone() + "_typed";
+        let name_exists = |name: &str| -> Result<bool> {
+            let count: i64 = conn.query_row(
+                "SELECT count(*) FROM sqlite_master WHERE name = ?1 COLLATE NOCASE",
+                [name],
+                |row| row.get(0),
+            )?;
+            Ok(count > 0)
+        };
+        while name_exists(&new_name)? {
+            new_name.push('_');
+        }
+
+        let columns = schema
+            .columns
+            .iter()
+            .map(|col| column_definition(parent, col))
+            .collect::<Vec<_>>();
+        let existing = schema.columns[..self.declared_types.len()]
+            .iter()
+            .map(|col| quote_identifier(col.name()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        // Copying values into typed columns applies their affinity, other tables
+        // keep referring to the table by its name
+        conn.execute_batch(&format!(
+            "CREATE TABLE {new} ({columns});
+            INSERT INTO {new} ({existing}) SELECT {existing} FROM {table};
+            DROP TABLE {table};
+            ALTER TABLE {new} RENAME TO {table};",
+            new = quote_identifier(&new_name),
+            table = quote_identifier(&schema.name),
+            columns = columns.join(", "),
+            existing = existing,
+        ))
+        .with_context(|| format!("Could not change column types of table {}", schema.name))?;
+        Ok(())
+    }
+
+    pub fn write(&mut self, conn: &Connection, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(conn, &Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, conn: &Connection, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+        self.sync_columns(conn)?;
+
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        let values = row_values(schema, row, &self.cells);
+        let mut statement = conn.prepare_cached(&self.insert_sql)?;
+        statement
+            .execute(params_from_iter(values.iter().map(|v| to_sql_value(v))))
+            .with_context(|| format!("Could not insert into table {}", self.name()))?;
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        self.schema
+            .as_ref()
+            .map(|schema| schema.name.as_str())
+            .unwrap_or_default()
+    }
+
+    fn key_name(&self) -> &str {
+        self.schema
+            .as_ref()
+            .and_then(|schema| schema.primary_key())
+            .map(|key| key.name.as_str())
+            .unwrap_or_default()
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Writes all tables into single sqlite database file
+///
+/// Tables get primary key and foreign key to parent table, rows are inserted
+/// in transactions of `batch_size` rows. Column types follow the values seen so far.
+pub struct DatabaseSqlite {
+    schema: DatabaseSchema,
+    path: PathBuf,
+    options: SqliteOptions,
+    conn: Connection,
+    // Rows inserted in current transaction
+    pending_rows: usize,
+    tables: HashMap<Vec<JsonPath>, TableSqlite>,
+}
+
+impl DatabaseSqlite {
+    pub fn new(
+        mut schema: DatabaseSchema,
+        path: PathBuf,
+        options: SqliteOptions,
+    ) -> Result<DatabaseSqlite> {
+        ensure_dir_exists_and_empty(&path)?;
+
+        let db_path = path.join(&options.file_name);
+        let conn = Connection::open(&db_path)
+            .with_context(|| format!("Could not create database {}", db_path.to_string_lossy()))?;
+        // Nested objects are written before their parents, so keys are only
+        // declared and not enforced while loading
+        conn.execute_batch("PRAGMA foreign_keys = OFF; BEGIN")?;
+
+        // Sqlite matches identifiers case-insensitively
+        if schema.dialect() == Dialect::Generic {
+            schema.set_dialect(Dialect::Sqlite);
+        }
+
+        Ok(DatabaseSqlite {
+            schema,
+            path,
+            options,
+            conn,
+            pending_rows: 0,
+            tables: HashMap::new(),
+        })
+    }
+
+    /// Parent tables are created first, as sqlite checks foreign keys
+    /// to refer to existing tables when altering
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableSqlite> {
+        if !self.tables.contains_key(table_path) {
+            let parent = match table_path.split_last() {
+                Some((_, parent_path)) => {
+                    self.get_or_create_table_mut(parent_path)?;
+                    let parent = self.tables.get_mut(parent_path).unwrap();
+                    parent.sync_columns(&self.conn)?;
+                    Some((parent.name().to_string(), parent.key_name().to_string()))
+                }
+                None => None,
+            };
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+            self.tables
+                .insert(table_path.to_vec(), TableSqlite::new(table_schema, parent));
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+}
+
+impl Database for DatabaseSqlite {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        self.write_row(&Row::from_record(&loc, &record))
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        self.get_or_create_table_mut(row.table_path)?;
+        let table = self.tables.get_mut(row.table_path).unwrap();
+        table.write_row(&self.conn, row)?;
+
+        self.pending_rows += 1;
+        if self.pending_rows >= self.options.batch_size {
+            self.conn
+                .execute_batch("COMMIT; BEGIN")
+                .context("Could not commit rows")?;
+            self.pending_rows = 0;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        // Parent tables registered only to name foreign keys may have no rows yet
+        for table in self.tables.values_mut() {
+            table.sync_columns(&self.conn)?;
+        }
+        if !self.conn.is_autocommit() {
+            self.conn
+                .execute_batch("COMMIT")
+                .context("Could not commit rows")?;
+        }
+
+        for (table_path, table) in self.tables.iter_mut() {
+            self.schema.return_table_schema(
+                table_path,
+                table
+                    .pop_schema()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        write_schema(&self.path, &self.schema).map_err(Error::from)
+    }
+}
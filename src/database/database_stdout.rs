@@ -1,35 +1,44 @@
-use anyhow::Result;
-
 use crate::database::DatabaseSchema;
 use crate::parser::{TableLocation, TableRecord};
+use crate::Error;
 
 use super::Database;
 
 pub struct TableStdout {}
 
-pub struct DatabaseStdout {}
+pub struct DatabaseStdout {
+    schema: DatabaseSchema,
+}
+
+impl Default for DatabaseStdout {
+    fn default() -> Self {
+        DatabaseStdout::new()
+    }
+}
 
 impl DatabaseStdout {
     pub fn new() -> DatabaseStdout {
-        DatabaseStdout {}
+        DatabaseStdout {
+            schema: DatabaseSchema::empty(),
+        }
     }
 }
 
 impl Database for DatabaseStdout {
     fn get_schema(&self) -> &DatabaseSchema {
-        todo!()
+        &self.schema
     }
 
     fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
-        todo!()
+        &mut self.schema
     }
 
-    fn write(&mut self, table: TableLocation, record: TableRecord) -> Result<()> {
+    fn write(&mut self, table: TableLocation, record: TableRecord) -> Result<(), Error> {
         println!("{:?}: {:?}", table, record);
         Ok(())
     }
 
-    fn close(&mut self) -> Result<()> {
+    fn close(&mut self) -> Result<(), Error> {
         Ok(())
     }
 }
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::database::{DatabaseSchema, Dialect};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::Database;
+
+/// Forwards every record to several sinks, e.g. csv for archiving and sqlite for inspection
+///
+/// Sinks must be constructed from copies of the same schema. Tables are added to all
+/// of them before the first row is forwarded, so that they get the same names in the same
+/// order whichever sink would create them first. The sinks then see the same records and
+/// grow identical schemas, which is checked on close, so `get_schema` returns the one
+/// of the first sink. Errors name the sink that failed.
+///
+/// Schemas are changed through `for_each_schema_mut`, as `get_schema_mut` would reach
+/// the first sink only.
+pub struct DatabaseTee {
+    sinks: Vec<(String, Box<dyn Database>)>,
+    // Tables already added to the schemas of all sinks
+    tables: HashSet<Vec<JsonPath>>,
+    // Some sink failed to take a record the others may have taken
+    failed: bool,
+}
+
+impl DatabaseTee {
+    /// Combine named sinks, none of which has written records yet
+    ///
+    /// Sinks switching a generic schema to their own dialect get it applied to the
+    /// others too, so that names match across sinks.
+    pub fn new(mut sinks: Vec<(String, Box<dyn Database>)>) -> Result<DatabaseTee> {
+        if sinks.is_empty() {
+            bail!("Tee needs at least one sink");
+        }
+
+        let mut dialects = sinks
+            .iter()
+            .map(|(_, sink)| sink.get_schema().dialect())
+            .filter(|dialect| *dialect != Dialect::Generic)
+            .collect::<Vec<_>>();
+        dialects.dedup();
+        match dialects.as_slice() {
+            [] => {}
+            [dialect] => {
+                for (_, sink) in sinks.iter_mut() {
+                    if sink.get_schema().dialect() != *dialect {
+                        sink.get_schema_mut().set_dialect(*dialect);
+                    }
+                }
+            }
+            _ => bail!(
+                "Sinks {} use different dialects, choose one for all of them",
+                sinks
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+
+        Ok(DatabaseTee {
+            sinks,
+            tables: HashSet::new(),
+            failed: false,
+        })
+    }
+
+    /// Names of sinks in the order records are forwarded to them
+    pub fn sink_names(&self) -> impl Iterator<Item = &str> {
+        self.sinks.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Apply change, e.g. `configure` or `set_naming`, to the schemas of all sinks
+    pub fn for_each_schema_mut<F: FnMut(&mut DatabaseSchema)>(&mut self, mut change: F) {
+        for (_, sink) in self.sinks.iter_mut() {
+            change(sink.get_schema_mut());
+        }
+    }
+
+    /// Add table of `table_path` with its parents first to all sinks, unless added before
+    fn add_table(&mut self, table_path: &[JsonPath]) {
+        if self.tables.contains(table_path) {
+            return;
+        }
+        for depth in 0..=table_path.len() {
+            let path = &table_path[..depth];
+            if self.tables.insert(path.to_vec()) {
+                for (_, sink) in self.sinks.iter_mut() {
+                    sink.get_schema_mut().add_table(path);
+                }
+            }
+        }
+    }
+}
+
+impl Database for DatabaseTee {
+    fn get_schema(&self) -> &DatabaseSchema {
+        self.sinks[0].1.get_schema()
+    }
+
+    /// Panics, as changing the schema of one sink only would make sinks diverge,
+    /// use `DatabaseTee::for_each_schema_mut` instead
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        panic!("Schemas of tee sinks are changed by DatabaseTee::for_each_schema_mut")
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        self.write_row(&Row::from_record(&loc, &record))
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        self.add_table(row.table_path);
+        for (name, sink) in self.sinks.iter_mut() {
+            if let Err(e) = sink.write_row(row) {
+                self.failed = true;
+                return Err(e.context(format!("Sink {} failed", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Close all sinks, even after one of them failed, then check their schemas match
+    /// unless they saw different records
+    fn close(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for (name, sink) in self.sinks.iter_mut() {
+            if let Err(e) = sink.close() {
+                if result.is_ok() {
+                    result = Err(e.context(format!("Sink {} failed", name)));
+                }
+            }
+        }
+        result?;
+        if self.failed {
+            return Ok(());
+        }
+
+        let (first_name, first) = &self.sinks[0];
+        let schema_value = |schema: &DatabaseSchema| {
+            serde_json::to_value(schema).map_err(|e| Error::Schema(e.into()))
+        };
+        let first_schema = schema_value(first.get_schema())?;
+        for (name, sink) in self.sinks[1..].iter() {
+            if schema_value(sink.get_schema())? != first_schema {
+                let error = anyhow!("Sinks {} and {} wrote different schemas", first_name, name);
+                return Err(Error::Schema(error));
+            }
+        }
+        Ok(())
+    }
+}
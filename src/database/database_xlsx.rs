@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+use std::mem::swap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rust_xlsxwriter::{ColNum, Format, RowNum, Workbook, Worksheet};
+use serde_json::Value;
+
+use crate::database::ddl::tables_parents_first;
+use crate::database::{DatabaseSchema, TableSchema};
+use crate::parser::{JsonPath, Row, TableLocation, TableRecord};
+use crate::Error;
+
+use super::output::{ensure_dir_exists_and_empty, row_values, write_schema};
+use super::Database;
+
+/// Name of workbook file unless configured otherwise
+pub const DEFAULT_FILE_NAME: &str = "tables.xlsx";
+
+/// Number of data rows per sheet, one row of a sheet is taken by the header
+pub const MAX_SHEET_ROWS: usize = 1_048_575;
+
+/// Name of sheet describing the tables
+pub const INDEX_SHEET_NAME: &str = "index";
+
+const MAX_COLUMNS: usize = 16_384;
+const MAX_SHEET_NAME_LEN: usize = 31;
+const MAX_STRING_LEN: usize = 32_767;
+// Larger integers lose precision as excel numbers and are written as text
+const MAX_SAFE_INTEGER: u64 = 1 << 53;
+
+#[derive(Clone)]
+pub struct XlsxOptions {
+    /// Name of workbook file within output directory
+    pub file_name: String,
+    /// Number of data rows after which a table continues on the next sheet
+    pub sheet_rows: usize,
+}
+
+impl Default for XlsxOptions {
+    fn default() -> Self {
+        XlsxOptions {
+            file_name: String::from(DEFAULT_FILE_NAME),
+            sheet_rows: MAX_SHEET_ROWS,
+        }
+    }
+}
+
+/// Write value as cell typed by the value itself
+///
+/// Fails on text longer than excel allows in a cell, rather than cutting it.
+fn write_cell(sheet: &mut Worksheet, row: RowNum, col: ColNum, v: &Value) -> Result<()> {
+    match v {
+        Value::Null => {}
+        Value::Bool(v) => {
+            sheet.write_boolean(row, col, *v)?;
+        }
+        Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) if i.unsigned_abs() <= MAX_SAFE_INTEGER => {
+                sheet.write_number(row, col, i as f64)?;
+            }
+            // Integers not fitting i64 are no floats either
+            (None, Some(f)) if n.is_f64() => {
+                sheet.write_number(row, col, f)?;
+            }
+            _ => {
+                sheet.write_string(row, col, n.to_string())?;
+            }
+        },
+        Value::String(s) => {
+            sheet.write_string(row, col, check_length(s)?)?;
+        }
+        v => {
+            sheet.write_string(row, col, check_length(&v.to_string())?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that text fits the cell limit of excel
+fn check_length(s: &str) -> Result<&str> {
+    let len = s.chars().count();
+    if len > MAX_STRING_LEN {
+        let error = anyhow!(
+            "Text of {} characters exceeds the limit of {} in excel cell",
+            len,
+            MAX_STRING_LEN
+        );
+        return Err(Error::Sink(error).into());
+    }
+    Ok(s)
+}
+
+/// Sheet name without characters excel rejects, unique among `used` names
+fn sheet_name(name: &str, used: &mut HashSet<String>) -> String {
+    let base = name
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let base = base.trim_matches('\'');
+    let base = if base.is_empty() { "table" } else { base };
+
+    let mut suffix = 1;
+    loop {
+        let suffix_str = if suffix == 1 {
+            String::new()
+        } else {
+            format!("_{}", suffix)
+        };
+        let len = MAX_SHEET_NAME_LEN - suffix_str.len();
+        let name = base.chars().take(len).collect::<String>() + &suffix_str;
+        // Excel compares sheet names case-insensitively
+        if used.insert(name.to_lowercase()) {
+            return name;
+        }
+        suffix += 1;
+    }
+}
+
+/// Worksheets of a single table, continued on new sheet once `sheet_rows` are written
+pub struct TableXlsx {
+    sheets: Vec<Worksheet>,
+    // Data rows written to the last sheet
+    sheet_rows: usize,
+    options: XlsxOptions,
+    // Buffer reused between rows
+    cells: Vec<Option<usize>>,
+    schema: Option<TableSchema>,
+}
+
+impl TableXlsx {
+    pub fn new(schema: TableSchema, options: XlsxOptions) -> TableXlsx {
+        TableXlsx {
+            sheets: Vec::new(),
+            sheet_rows: 0,
+            options,
+            cells: Vec::new(),
+            schema: Some(schema),
+        }
+    }
+
+    pub fn write(&mut self, loc: TableLocation, rec: TableRecord) -> Result<()> {
+        self.write_row(&Row::from_record(&loc, &rec))
+    }
+
+    pub fn write_row(&mut self, row: &Row<'_>) -> Result<()> {
+        let schema = self
+            .schema
+            .as_mut()
+            .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+        schema.update_values(&row.values, &mut self.cells);
+        let values = row_values(schema, row, &self.cells);
+        if values.len() > MAX_COLUMNS {
+            bail!(
+                "Table {} has more than {} columns allowed in excel sheet",
+                schema.name,
+                MAX_COLUMNS
+            );
+        }
+
+        if self.sheets.is_empty() || self.sheet_rows >= self.options.sheet_rows {
+            self.sheets.push(Worksheet::new());
+            self.sheet_rows = 0;
+        }
+        let sheet = self.sheets.last_mut().unwrap();
+        self.sheet_rows += 1;
+        for (col_id, (col, v)) in schema.columns.iter().zip(values.iter()).enumerate() {
+            write_cell(sheet, self.sheet_rows as RowNum, col_id as ColNum, v).with_context(
+                || {
+                    format!(
+                        "Could not write column {} of table {}",
+                        col.name(),
+                        schema.name
+                    )
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Name sheets and write headers, with columns known at the end
+    ///
+    /// Returns names of sheets along with the number of data rows on each.
+    fn finish_sheets(
+        &mut self,
+        schema: &TableSchema,
+        used_names: &mut HashSet<String>,
+    ) -> Result<Vec<(String, usize)>> {
+        let header_format = Format::new().set_bold();
+        if self.sheets.is_empty() {
+            self.sheets.push(Worksheet::new());
+        }
+
+        let mut names = Vec::new();
+        let last = self.sheets.len() - 1;
+        for (i, sheet) in self.sheets.iter_mut().enumerate() {
+            let name = sheet_name(&schema.name, used_names);
+            sheet.set_name(&name)?;
+            for (col_id, col) in schema.columns.iter().enumerate() {
+                sheet.write_string_with_format(0, col_id as ColNum, col.name(), &header_format)?;
+            }
+            sheet.set_freeze_panes(1, 0)?;
+            let rows = if i == last {
+                self.sheet_rows
+            } else {
+                self.options.sheet_rows
+            };
+            names.push((name, rows));
+        }
+        Ok(names)
+    }
+
+    pub fn pop_schema(&mut self) -> Option<TableSchema> {
+        let mut schema: Option<TableSchema> = None;
+        swap(&mut self.schema, &mut schema);
+        schema
+    }
+}
+
+/// Writes single `.xlsx` workbook with a sheet per table
+///
+/// The first sheet lists tables with their sheets, json paths and parent tables.
+/// Tables with more rows than fit a sheet continue on sheets with numbered names.
+pub struct DatabaseXlsx {
+    schema: DatabaseSchema,
+    path: PathBuf,
+    options: XlsxOptions,
+    tables: HashMap<Vec<JsonPath>, TableXlsx>,
+}
+
+impl DatabaseXlsx {
+    pub fn new(
+        schema: DatabaseSchema,
+        path: PathBuf,
+        options: XlsxOptions,
+    ) -> Result<DatabaseXlsx> {
+        ensure_dir_exists_and_empty(&path)?;
+
+        Ok(DatabaseXlsx {
+            schema,
+            path,
+            options,
+            tables: HashMap::new(),
+        })
+    }
+
+    fn get_or_create_table_mut(&mut self, table_path: &[JsonPath]) -> Result<&mut TableXlsx> {
+        if !self.tables.contains_key(table_path) {
+            let table_schema = self
+                .schema
+                .borrow_table_schema(table_path)
+                .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?;
+            self.tables.insert(
+                table_path.to_vec(),
+                TableXlsx::new(table_schema, self.options.clone()),
+            );
+        }
+        Ok(self.tables.get_mut(table_path).unwrap())
+    }
+
+    /// Sheet listing tables, one row per sheet of a table with the number of its rows
+    fn index_sheet(&self, sheets: &HashMap<String, Vec<(String, usize)>>) -> Result<Worksheet> {
+        let mut index = Worksheet::new();
+        index.set_name(INDEX_SHEET_NAME)?;
+        let header_format = Format::new().set_bold();
+        for (col_id, header) in ["sheet", "table", "path", "parent", "rows"]
+            .iter()
+            .enumerate()
+        {
+            index.write_string_with_format(0, col_id as ColNum, *header, &header_format)?;
+        }
+        index.set_freeze_panes(1, 0)?;
+
+        let mut row: RowNum = 0;
+        for table in tables_parents_first(&self.schema) {
+            let parent = match table.path.split_last() {
+                Some((_, parent_path)) => self.schema.table(parent_path),
+                None => None,
+            };
+            for (sheet, rows) in sheets.get(&table.name).into_iter().flatten() {
+                row += 1;
+                index.write_string(row, 0, sheet)?;
+                index.write_string(row, 1, &table.name)?;
+                index.write_string(row, 2, serde_json::to_string(&table.path)?)?;
+                if let Some(parent) = parent {
+                    index.write_string(row, 3, &parent.name)?;
+                }
+                index.write_number(row, 4, *rows as f64)?;
+            }
+        }
+        Ok(index)
+    }
+}
+
+impl Database for DatabaseXlsx {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, loc: TableLocation, record: TableRecord) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(&loc.table_path)?;
+        table.write(loc, record).map_err(Error::from)
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        let table = self.get_or_create_table_mut(row.table_path)?;
+        table.write_row(row).map_err(Error::from)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        for (table_path, table) in self.tables.iter_mut() {
+            self.schema.return_table_schema(
+                table_path,
+                table
+                    .pop_schema()
+                    .ok_or_else(|| Error::Schema(anyhow!("Table schema was already returned")))?,
+            )?;
+        }
+
+        self.schema.ensure_all_tables_returned()?;
+
+        // Index sheet comes first and takes its name
+        let mut used_names = HashSet::from([INDEX_SHEET_NAME.to_string()]);
+        let mut sheets = HashMap::new();
+        let mut table_sheets = Vec::new();
+        for table_schema in tables_parents_first(&self.schema) {
+            if let Some(table) = self.tables.get_mut(&table_schema.path) {
+                let names = table.finish_sheets(table_schema, &mut used_names)?;
+                sheets.insert(table_schema.name.clone(), names);
+                table_sheets.append(&mut table.sheets);
+            }
+        }
+
+        let mut workbook = Workbook::new();
+        workbook.push_worksheet(self.index_sheet(&sheets)?);
+        for sheet in table_sheets {
+            workbook.push_worksheet(sheet);
+        }
+        let workbook_path = self.path.join(&self.options.file_name);
+        workbook.save(&workbook_path).with_context(|| {
+            format!(
+                "Could not write workbook {}",
+                workbook_path.to_string_lossy()
+            )
+        })?;
+
+        write_schema(&self.path, &self.schema).map_err(Error::from)
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt::Write;
+use std::string::String;
+
+use anyhow::{anyhow, Result};
+
+use crate::database::{ColumnSchema, DatabaseSchema, Dialect, TableSchema, ValueType};
+use crate::Error;
+
+/// Quote identifier, names are used as is so that quoting keeps their case
+pub fn quote_identifier(dialect: Dialect, name: &str) -> String {
+    match dialect {
+        Dialect::Mysql | Dialect::Bigquery => String::from("`") + &name.replace('`', "``") + "`",
+        _ => String::from("\"") + &name.replace('"', "\"\"") + "\"",
+    }
+}
+
+/// Quote string literal
+pub fn quote_string(dialect: Dialect, s: &str) -> String {
+    match dialect {
+        Dialect::Mysql => String::from("'") + &s.replace('\\', "\\\\").replace('\'', "\\'") + "'",
+        // Line breaks are not allowed within quotes
+        Dialect::Bigquery => {
+            let escaped = s
+                .replace('\\', "\\\\")
+                .replace('\'', "\\'")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r");
+            String::from("'") + &escaped + "'"
+        }
+        Dialect::Snowflake => {
+            String::from("'") + &s.replace('\\', "\\\\").replace('\'', "''") + "'"
+        }
+        _ => String::from("'") + &s.replace('\'', "''") + "'",
+    }
+}
+
+/// Quote argument of shell command
+fn quote_shell(s: &str) -> String {
+    String::from("'") + &s.replace('\'', "'\\''") + "'"
+}
+
+/// Column type for values of `value_type`, keys use `ValueType::Integer`
+pub fn column_type(dialect: Dialect, value_type: ValueType) -> &'static str {
+    match (dialect, value_type) {
+        (Dialect::Bigquery, ValueType::Bool) => "BOOL",
+        (Dialect::Bigquery, ValueType::Integer) => "INT64",
+        (Dialect::Bigquery, ValueType::Float) => "FLOAT64",
+        (Dialect::Bigquery, _) => "STRING",
+        (Dialect::Sqlite, ValueType::Bool) => "INTEGER",
+        (Dialect::Sqlite, ValueType::Integer) => "INTEGER",
+        (Dialect::Sqlite, ValueType::Float) => "REAL",
+        (Dialect::Sqlite, _) => "TEXT",
+        (Dialect::Mysql, ValueType::Float) => "DOUBLE",
+        (Dialect::Mysql, ValueType::Null | ValueType::String) => "LONGTEXT",
+        (Dialect::Snowflake, ValueType::Float) => "DOUBLE",
+        (Dialect::Snowflake, ValueType::Null | ValueType::String) => "VARCHAR",
+        (_, ValueType::Bool) => "BOOLEAN",
+        (_, ValueType::Integer) => "BIGINT",
+        (_, ValueType::Float) => "DOUBLE PRECISION",
+        (_, ValueType::Null | ValueType::String) => "TEXT",
+    }
+}
+
+fn check_dialect(schema: &DatabaseSchema) -> Result<Dialect> {
+    match schema.dialect() {
+        Dialect::Generic => Err(Error::Schema(anyhow!(
+            "Schema has no target dialect, names may be invalid"
+        ))
+        .into()),
+        dialect => Ok(dialect),
+    }
+}
+
+/// Tables ordered so that parents come before children
+pub fn tables_parents_first(schema: &DatabaseSchema) -> Vec<&TableSchema> {
+    let mut tables = schema.tables().collect::<Vec<_>>();
+    tables.sort_by_key(|table| table.path.len());
+    tables
+}
+
+fn create_table(schema: &DatabaseSchema, dialect: Dialect, table: &TableSchema) -> String {
+    let q = |name: &str| quote_identifier(dialect, name);
+    // Constraints are informational only in bigquery
+    let not_enforced = if dialect == Dialect::Bigquery {
+        " NOT ENFORCED"
+    } else {
+        ""
+    };
+
+    let mut lines = table
+        .columns
+        .iter()
+        .map(|col| match col {
+            ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => {
+                let key_type = if dialect == Dialect::Mysql {
+                    "INT"
+                } else {
+                    column_type(dialect, ValueType::Integer)
+                };
+                format!("{} {} NOT NULL", q(col.name()), key_type)
+            }
+            ColumnSchema::SourceColumn(col) => {
+                let not_null = if col.is_nullable { "" } else { " NOT NULL" };
+                format!(
+                    "{} {}{}",
+                    q(&col.name),
+                    column_type(dialect, col.value_type()),
+                    not_null
+                )
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(key) = table.primary_key() {
+        lines.push(format!("PRIMARY KEY ({}){}", q(&key.name), not_enforced));
+    }
+    let mut parent_path = table.path.clone();
+    parent_path.pop();
+    let parent = schema
+        .table(&parent_path)
+        .filter(|_| !table.path.is_empty());
+    if let (Some(key), Some(parent)) = (table.foreign_key(), parent) {
+        if let Some(parent_key) = parent.primary_key() {
+            lines.push(format!(
+                "FOREIGN KEY ({}) REFERENCES {} ({}){}",
+                q(&key.name),
+                q(&parent.name),
+                q(&parent_key.name),
+                not_enforced
+            ));
+        }
+    }
+
+    format!(
+        "CREATE TABLE {} (\n    {}\n);\n",
+        q(&table.name),
+        lines.join(",\n    ")
+    )
+}
+
+/// `CREATE TABLE` statements for all tables, using target dialect of the schema
+pub fn create_tables(schema: &DatabaseSchema) -> Result<String> {
+    let dialect = check_dialect(schema)?;
+    let mut ddl = String::new();
+    for table in tables_parents_first(schema) {
+        if !ddl.is_empty() {
+            ddl.push('\n');
+        }
+        ddl += &create_table(schema, dialect, table);
+    }
+    Ok(ddl)
+}
+
+fn load_table(dialect: Dialect, table: &TableSchema) -> String {
+    let q = |name: &str| quote_identifier(dialect, name);
+    let file = format!("data/{}.csv", table.name);
+    let columns = table
+        .columns
+        .iter()
+        .map(|col| q(col.name()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    match dialect {
+        // Unquoted empty fields are nulls, quoted ones are empty strings
+        Dialect::Postgres | Dialect::Generic => format!(
+            "\\copy {} ({}) FROM {} WITH (FORMAT csv, HEADER true)\n",
+            q(&table.name),
+            columns,
+            quote_string(dialect, &file)
+        ),
+        Dialect::Mysql => {
+            // Fields are read into variables, so that empty fields become nulls
+            // and booleans become numbers
+            let mut variables = Vec::new();
+            let mut assignments = Vec::new();
+            for (col_id, col) in table.columns.iter().enumerate() {
+                let variable = format!("@c{}", col_id);
+                let value = match col {
+                    ColumnSchema::SourceColumn(col) if col.value_type() == ValueType::Bool => {
+                        format!(
+                            "CASE {} WHEN 'true' THEN 1 WHEN 'false' THEN 0 END",
+                            variable
+                        )
+                    }
+                    _ => format!("NULLIF({}, '')", variable),
+                };
+                assignments.push(format!("{} = {}", q(col.name()), value));
+                variables.push(variable);
+            }
+            format!(
+                "LOAD DATA LOCAL INFILE {} INTO TABLE {}\n    CHARACTER SET utf8mb4\n    \
+                 FIELDS TERMINATED BY ',' OPTIONALLY ENCLOSED BY '\"' ESCAPED BY ''\n    \
+                 LINES TERMINATED BY '\\r\\n'\n    IGNORE 1 LINES\n    ({})\n    SET {};\n",
+                quote_string(dialect, &file),
+                q(&table.name),
+                variables.join(", "),
+                assignments.join(",\n        ")
+            )
+        }
+        Dialect::Sqlite => {
+            // Fields are imported as text, so empty fields become nulls and
+            // booleans become numbers afterwards. Empty strings become nulls too,
+            // as csv files do not tell them apart
+            let assignments = table
+                .columns
+                .iter()
+                .filter_map(|col| match col {
+                    ColumnSchema::SourceColumn(source) => {
+                        let name = q(col.name());
+                        Some(if source.value_type() == ValueType::Bool {
+                            format!(
+                                "{} = CASE {} WHEN 'true' THEN 1 WHEN 'false' THEN 0 END",
+                                name, name
+                            )
+                        } else {
+                            format!("{} = NULLIF({}, '')", name, name)
+                        })
+                    }
+                    ColumnSchema::PrimaryKey(_) | ColumnSchema::ForeignKey(_) => None,
+                })
+                .collect::<Vec<_>>();
+            let mut load = format!(
+                ".import --csv --skip 1 {} {}\n",
+                quote_string(dialect, &file),
+                q(&table.name)
+            );
+            if !assignments.is_empty() {
+                load += &format!(
+                    "UPDATE {} SET {};\n",
+                    q(&table.name),
+                    assignments.join(",\n    ")
+                );
+            }
+            load
+        }
+        Dialect::Bigquery => format!(
+            "bq load --source_format=CSV --skip_leading_rows=1 --allow_quoted_newlines {} {}\n",
+            quote_shell(&table.name),
+            quote_shell(&file)
+        ),
+        Dialect::Snowflake => format!(
+            "PUT {} @%{} OVERWRITE = TRUE;\n\
+             COPY INTO {} ({}) FROM @%{}\n    \
+             FILE_FORMAT = (TYPE = CSV SKIP_HEADER = 1 FIELD_OPTIONALLY_ENCLOSED_BY = '\"' \
+             EMPTY_FIELD_AS_NULL = TRUE);\n",
+            quote_string(dialect, &(String::from("file://") + &file)),
+            q(&table.name),
+            q(&table.name),
+            columns,
+            q(&table.name)
+        ),
+    }
+}
+
+/// Script loading csv files of `DatabaseCsv` into tables made by `create_tables`
+///
+/// Paths are relative to the output directory. Postgres and sqlite scripts are meant
+/// for `psql` and `sqlite3` clients, the bigquery one is a shell script for `bq`.
+pub fn load_script(schema: &DatabaseSchema) -> Result<String> {
+    let dialect = check_dialect(schema)?;
+    let mut script = String::new();
+    for table in tables_parents_first(schema) {
+        write!(script, "{}", load_table(dialect, table))?;
+    }
+    Ok(script)
+}
@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::string::String;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+#[cfg(feature = "arrow")]
+use crate::database::DatabaseArrow;
+#[cfg(feature = "parquet")]
+use crate::database::DatabaseParquet;
+#[cfg(feature = "postgres")]
+use crate::database::DatabasePostgres;
+#[cfg(feature = "sqlite")]
+use crate::database::DatabaseSqlite;
+#[cfg(feature = "xlsx")]
+use crate::database::DatabaseXlsx;
+use crate::database::{
+    Database, DatabaseAvro, DatabaseCsv, DatabaseJsonl, DatabaseSchema, DatabaseSql, DatabaseTee,
+};
+
+/// Settings a sink is constructed with
+pub struct FormatOptions {
+    /// Output directory path
+    pub output: PathBuf,
+    /// Format specific options, given as `key=value` pairs
+    pub options: BTreeMap<String, String>,
+}
+
+impl FormatOptions {
+    pub fn new(output: PathBuf) -> FormatOptions {
+        FormatOptions {
+            output,
+            options: BTreeMap::new(),
+        }
+    }
+
+    /// Add option given as `key=value`
+    pub fn push(&mut self, option: &str) -> Result<()> {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Option {} must have form key=value", option))?;
+        self.options.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// Parse value of option `key`, if it was given
+    pub fn get<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.options
+            .get(key)
+            .map(|value| {
+                value
+                    .parse::<T>()
+                    .with_context(|| format!("Invalid value {} of option {}", value, key))
+            })
+            .transpose()
+    }
+}
+
+pub type FormatConstructor = fn(DatabaseSchema, &FormatOptions) -> Result<Box<dyn Database>>;
+
+/// Output format that can be chosen by name
+pub struct Format {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Names and descriptions of accepted options
+    pub options: &'static [(&'static str, &'static str)],
+    constructor: FormatConstructor,
+}
+
+impl Format {
+    /// Construct sink, rejecting options the format does not know
+    pub fn open(
+        &self,
+        schema: DatabaseSchema,
+        options: &FormatOptions,
+    ) -> Result<Box<dyn Database>> {
+        for key in options.options.keys() {
+            if !self.options.iter().any(|(name, _)| name == key) {
+                bail!("Format {} has no option {}", self.name, key);
+            }
+        }
+        (self.constructor)(schema, options)
+    }
+}
+
+fn open_csv(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    Ok(Box::new(DatabaseCsv::new(schema, options.output.clone())?))
+}
+
+fn open_jsonl(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    Ok(Box::new(DatabaseJsonl::new(
+        schema,
+        options.output.clone(),
+    )?))
+}
+
+fn open_sql(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    use crate::database::database_sql::SqlOptions;
+
+    let mut sql_options = SqlOptions::default();
+    if let Some(batch_size) = options.get("batch_size")? {
+        sql_options.batch_size = batch_size;
+    }
+    if let Some(combined) = options.get("combined")? {
+        sql_options.combined = combined;
+    }
+    Ok(Box::new(DatabaseSql::new(
+        schema,
+        options.output.clone(),
+        sql_options,
+    )?))
+}
+
+fn open_avro(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    use crate::database::database_avro::AvroOptions;
+
+    let mut avro_options = AvroOptions::default();
+    if let Some(block_size) = options.get("block_size")? {
+        avro_options.block_size = block_size;
+    }
+    Ok(Box::new(DatabaseAvro::new(
+        schema,
+        options.output.clone(),
+        avro_options,
+    )?))
+}
+
+#[cfg(feature = "arrow")]
+fn open_arrow(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    use crate::database::database_arrow::ArrowOptions;
+
+    let mut arrow_options = ArrowOptions::default();
+    if let Some(batch_size) = options.get("batch_size")? {
+        arrow_options.batch_size = batch_size;
+    }
+    if arrow_options.batch_size == 0 {
+        bail!("Option batch_size must be positive");
+    }
+    Ok(Box::new(DatabaseArrow::with_ipc(
+        schema,
+        options.output.clone(),
+        arrow_options,
+    )?))
+}
+
+#[cfg(feature = "parquet")]
+fn open_parquet(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    use crate::database::database_parquet::ParquetOptions;
+
+    let mut parquet_options = ParquetOptions::default();
+    if let Some(row_group_size) = options.get("row_group_size")? {
+        parquet_options.row_group_size = row_group_size;
+    }
+    if let Some(compression) = options.get("compression")? {
+        parquet_options.compression = compression;
+    }
+    if parquet_options.row_group_size == 0 {
+        bail!("Option row_group_size must be positive");
+    }
+    Ok(Box::new(DatabaseParquet::new(
+        schema,
+        options.output.clone(),
+        parquet_options,
+    )?))
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    use crate::database::database_sqlite::SqliteOptions;
+
+    let mut sqlite_options = SqliteOptions::default();
+    if let Some(file_name) = options.get("file")? {
+        sqlite_options.file_name = file_name;
+    }
+    if let Some(batch_size) = options.get("batch_size")? {
+        sqlite_options.batch_size = batch_size;
+    }
+    if sqlite_options.batch_size == 0 {
+        bail!("Option batch_size must be positive");
+    }
+    Ok(Box::new(DatabaseSqlite::new(
+        schema,
+        options.output.clone(),
+        sqlite_options,
+    )?))
+}
+
+#[cfg(feature = "postgres")]
+fn open_postgres(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    use crate::database::database_postgres::PostgresOptions;
+
+    let url = options
+        .get("url")?
+        .ok_or_else(|| anyhow!("Format postgres requires option url"))?;
+    let mut postgres_options = PostgresOptions::new(url);
+    postgres_options.schema_name = options.get("schema")?;
+    if let Some(batch_size) = options.get("batch_size")? {
+        postgres_options.batch_size = batch_size;
+    }
+    Ok(Box::new(DatabasePostgres::new(
+        schema,
+        options.output.clone(),
+        postgres_options,
+    )?))
+}
+
+#[cfg(feature = "xlsx")]
+fn open_xlsx(schema: DatabaseSchema, options: &FormatOptions) -> Result<Box<dyn Database>> {
+    use crate::database::database_xlsx::{XlsxOptions, MAX_SHEET_ROWS};
+
+    let mut xlsx_options = XlsxOptions::default();
+    if let Some(file_name) = options.get("file")? {
+        xlsx_options.file_name = file_name;
+    }
+    if let Some(sheet_rows) = options.get("sheet_rows")? {
+        xlsx_options.sheet_rows = sheet_rows;
+    }
+    if xlsx_options.sheet_rows == 0 || xlsx_options.sheet_rows > MAX_SHEET_ROWS {
+        bail!("Option sheet_rows must be between 1 and {}", MAX_SHEET_ROWS);
+    }
+    Ok(Box::new(DatabaseXlsx::new(
+        schema,
+        options.output.clone(),
+        xlsx_options,
+    )?))
+}
+
+const FORMATS: &[Format] = &[
+    Format {
+        name: "csv",
+        description: "Csv file per table with header row",
+        options: &[],
+        constructor: open_csv,
+    },
+    Format {
+        name: "jsonl",
+        description: "Json lines file per table, keeping json types of values",
+        options: &[],
+        constructor: open_jsonl,
+    },
+    Format {
+        name: "sql",
+        description: "Sql file of INSERT statements per table, quoted for --dialect",
+        options: &[
+            ("batch_size", "Number of rows in single INSERT statement"),
+            (
+                "combined",
+                "true to write single data.sql file, parents before children",
+            ),
+        ],
+        constructor: open_sql,
+    },
+    Format {
+        name: "avro",
+        description: "Avro container file per table with record schema derived from columns",
+        options: &[("block_size", "Number of rows in data block")],
+        constructor: open_avro,
+    },
+    #[cfg(feature = "arrow")]
+    Format {
+        name: "arrow",
+        description: "Arrow ipc (feather) file per table with column types inferred from values",
+        options: &[("batch_size", "Maximum number of rows in record batch")],
+        constructor: open_arrow,
+    },
+    #[cfg(feature = "parquet")]
+    Format {
+        name: "parquet",
+        description: "Parquet file per table with column types inferred from values",
+        options: &[
+            ("row_group_size", "Maximum number of rows in row group"),
+            ("compression", "uncompressed or snappy"),
+        ],
+        constructor: open_parquet,
+    },
+    #[cfg(feature = "sqlite")]
+    Format {
+        name: "sqlite",
+        description: "Sqlite database file with primary and foreign keys",
+        options: &[
+            ("file", "Name of database file, database.sqlite by default"),
+            (
+                "batch_size",
+                "Number of rows inserted in single transaction",
+            ),
+        ],
+        constructor: open_sqlite,
+    },
+    #[cfg(feature = "postgres")]
+    Format {
+        name: "postgres",
+        description: "Tables loaded into postgres through COPY, only schema.json is written",
+        options: &[
+            (
+                "url",
+                "Connection string, e.g. postgresql://user@localhost/db",
+            ),
+            ("schema", "Postgres schema to create tables in"),
+            (
+                "batch_size",
+                "Number of rows buffered per table before copying",
+            ),
+        ],
+        constructor: open_postgres,
+    },
+    #[cfg(feature = "xlsx")]
+    Format {
+        name: "xlsx",
+        description: "Excel workbook with a sheet per table and an index sheet",
+        options: &[
+            ("file", "Name of workbook file, tables.xlsx by default"),
+            (
+                "sheet_rows",
+                "Number of rows after which a table continues on the next sheet",
+            ),
+        ],
+        constructor: open_xlsx,
+    },
+];
+
+/// All built-in output formats
+pub fn formats() -> &'static [Format] {
+    FORMATS
+}
+
+/// Look up built-in output format by name
+pub fn format(name: &str) -> Option<&'static Format> {
+    FORMATS.iter().find(|format| format.name == name)
+}
+
+/// Options of format `name` within a tee writing to a subdirectory per format
+///
+/// Options given as `name.key=value` only apply to that format, plain ones
+/// to every format accepting them.
+fn tee_options(name: &str, options: &FormatOptions) -> FormatOptions {
+    let mut format_options = FormatOptions::new(options.output.join(name));
+    let accepted = format(name)
+        .map(|format| format.options)
+        .unwrap_or_default();
+    for (key, value) in options.options.iter() {
+        let key = match key.split_once('.') {
+            Some((prefix, key)) if prefix == name => key,
+            Some(_) => continue,
+            None => key,
+        };
+        if accepted.iter().any(|(option, _)| *option == key) {
+            format_options
+                .options
+                .insert(key.to_string(), value.clone());
+        }
+    }
+    format_options
+}
+
+/// Construct sink of format `name`
+///
+/// Several formats separated by commas, e.g. `csv,sqlite`, are written at once
+/// through `DatabaseTee`, each into a subdirectory of the output named after the format.
+pub fn open_database(
+    name: &str,
+    schema: DatabaseSchema,
+    options: &FormatOptions,
+) -> Result<Box<dyn Database>> {
+    if !name.contains(',') {
+        return format(name)
+            .ok_or_else(|| anyhow!("Unknown format {}", name))?
+            .open(schema, options);
+    }
+
+    let names = name.split(',').collect::<Vec<_>>();
+    for key in options.options.keys() {
+        let is_accepted = names.iter().any(|name| {
+            let key = match key.split_once('.') {
+                Some((prefix, key)) if prefix == *name => key,
+                Some(_) => return false,
+                None => key,
+            };
+            format(name).is_some_and(|format| format.options.iter().any(|(o, _)| *o == key))
+        });
+        if !is_accepted {
+            bail!("None of formats {} has option {}", name, key);
+        }
+    }
+
+    let mut sinks = Vec::new();
+    for name in names {
+        if sinks.iter().any(|(sink_name, _)| sink_name == name) {
+            bail!("Format {} is given more than once", name);
+        }
+        let sink = open_database(name, schema.clone(), &tee_options(name, options))?;
+        sinks.push((name.to_string(), sink));
+    }
+    Ok(Box::new(DatabaseTee::new(sinks)?))
+}
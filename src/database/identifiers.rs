@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::string::String;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::database::naming::{EscapedNaming, NamingStrategy};
+use crate::parser::JsonPath;
+
+/// Words that can not be used as identifiers without quoting in most sql databases
+const SQL_RESERVED_WORDS: &[&str] = &[
+    "all",
+    "alter",
+    "and",
+    "any",
+    "as",
+    "asc",
+    "between",
+    "by",
+    "case",
+    "cast",
+    "check",
+    "column",
+    "constraint",
+    "create",
+    "cross",
+    "current_date",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "default",
+    "delete",
+    "desc",
+    "distinct",
+    "drop",
+    "else",
+    "end",
+    "except",
+    "exists",
+    "false",
+    "fetch",
+    "for",
+    "foreign",
+    "from",
+    "full",
+    "grant",
+    "group",
+    "having",
+    "in",
+    "inner",
+    "insert",
+    "intersect",
+    "interval",
+    "into",
+    "is",
+    "join",
+    "key",
+    "left",
+    "like",
+    "limit",
+    "natural",
+    "not",
+    "null",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "outer",
+    "primary",
+    "references",
+    "right",
+    "select",
+    "set",
+    "some",
+    "table",
+    "then",
+    "to",
+    "true",
+    "union",
+    "unique",
+    "update",
+    "user",
+    "using",
+    "values",
+    "when",
+    "where",
+    "with",
+];
+
+/// Column name prefixes reserved by BigQuery
+const BIGQUERY_RESERVED_PREFIXES: &[&str] = &[
+    "_table_",
+    "_file_",
+    "_partition",
+    "_row_timestamp",
+    "__root__",
+    "_colidentifier",
+];
+
+/// Target database whose identifier rules names must follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Dialect {
+    /// Only characters that can not appear in file names are replaced
+    #[default]
+    Generic,
+    Postgres,
+    Mysql,
+    Sqlite,
+    Bigquery,
+    Snowflake,
+}
+
+impl FromStr for Dialect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(Dialect::Generic),
+            "postgres" => Ok(Dialect::Postgres),
+            "mysql" => Ok(Dialect::Mysql),
+            "sqlite" => Ok(Dialect::Sqlite),
+            "bigquery" => Ok(Dialect::Bigquery),
+            "snowflake" => Ok(Dialect::Snowflake),
+            _ => Err(anyhow!("Unknown dialect {}", s)),
+        }
+    }
+}
+
+impl Dialect {
+    /// Maximum identifier length in bytes
+    pub fn max_len(&self) -> Option<usize> {
+        match self {
+            Dialect::Generic => None,
+            Dialect::Postgres => Some(63),
+            Dialect::Mysql => Some(64),
+            Dialect::Sqlite => None,
+            Dialect::Bigquery => Some(300),
+            Dialect::Snowflake => Some(255),
+        }
+    }
+
+    /// Identifiers differing only in case refer to the same object
+    pub fn is_case_insensitive(&self) -> bool {
+        matches!(self, Dialect::Mysql | Dialect::Sqlite | Dialect::Bigquery)
+    }
+
+    fn is_allowed_char(&self, c: char) -> bool {
+        // Identifiers are also used as file names
+        if c == '\0' || c == '/' || c.is_control() {
+            return false;
+        }
+        match self {
+            Dialect::Bigquery => c.is_ascii_alphanumeric() || c == '_',
+            Dialect::Mysql => c != '\\' && c != '.',
+            _ => true,
+        }
+    }
+
+    fn is_reserved(&self, id: &str) -> bool {
+        let id = id.to_lowercase();
+        match self {
+            Dialect::Generic => false,
+            Dialect::Bigquery => {
+                SQL_RESERVED_WORDS.contains(&id.as_str())
+                    || BIGQUERY_RESERVED_PREFIXES
+                        .iter()
+                        .any(|prefix| id.starts_with(prefix))
+            }
+            _ => SQL_RESERVED_WORDS.contains(&id.as_str()),
+        }
+    }
+
+    /// Form of identifier used to detect collisions
+    pub fn fold(&self, id: &str) -> String {
+        if self.is_case_insensitive() {
+            id.to_lowercase()
+        } else {
+            id.to_string()
+        }
+    }
+
+    /// Make name valid identifier, truncating it with hash suffix if too long
+    pub fn identifier(&self, name: &str) -> String {
+        let mut id: String = name
+            .chars()
+            .map(|c| if self.is_allowed_char(c) { c } else { '_' })
+            .collect();
+        if *self == Dialect::Bigquery && id.starts_with(|c: char| c.is_ascii_digit()) {
+            id.insert(0, '_');
+        }
+        if id.is_empty() {
+            id.push('_');
+        }
+        if self.is_reserved(&id) {
+            id = String::from("c_") + &id;
+        }
+        match self.max_len() {
+            Some(max_len) if id.len() > max_len => self.with_hash(&id, name),
+            _ => id,
+        }
+    }
+
+    /// Replace end of identifier with hash of the original name
+    pub fn with_hash(&self, id: &str, name: &str) -> String {
+        let suffix = format!("_{:08x}", stable_hash(name) as u32);
+        let mut end = match self.max_len() {
+            Some(max_len) => id.len().min(max_len - suffix.len()),
+            None => id.len(),
+        };
+        while !id.is_char_boundary(end) {
+            end -= 1;
+        }
+        String::from(&id[..end]) + &suffix
+    }
+
+    /// Make identifier for name that does not collide with any of `used` folded identifiers
+    pub fn unique_identifier(&self, name: &str, used: &HashSet<String>) -> String {
+        let id = self.identifier(name);
+        if !used.contains(&self.fold(&id)) {
+            return id;
+        }
+        let mut attempt: usize = 0;
+        loop {
+            let salted = if attempt == 0 {
+                String::from(name)
+            } else {
+                format!("{}#{}", name, attempt)
+            };
+            let id = self.with_hash(&id, &salted);
+            if !used.contains(&self.fold(&id)) {
+                return id;
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// FNV-1a hash, stable across runs and platforms
+fn stable_hash(s: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Makes identifiers for tables and columns from json paths,
+/// applying naming strategy followed by rules of the target dialect
+#[derive(Clone)]
+pub struct Identifiers {
+    naming: Arc<dyn NamingStrategy>,
+    dialect: Dialect,
+    root_name: String,
+}
+
+impl Identifiers {
+    pub fn new(naming: Arc<dyn NamingStrategy>, dialect: Dialect, root_name: String) -> Self {
+        Identifiers {
+            naming,
+            dialect,
+            root_name,
+        }
+    }
+
+    pub fn naming(&self) -> &dyn NamingStrategy {
+        self.naming.as_ref()
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub fn root_name(&self) -> &str {
+        &self.root_name
+    }
+
+    /// Name of column holding values at `path`, before applying dialect rules
+    pub fn column_name(&self, path: &JsonPath) -> String {
+        self.naming.column_name(path)
+    }
+
+    /// Name of column holding ids of table at `table_path`, before applying dialect rules
+    pub fn key_column_name(&self, table_path: &[JsonPath]) -> String {
+        self.naming.key_column_name(&self.root_name, table_path)
+    }
+
+    /// Name of table at `table_path`, before applying dialect rules
+    pub fn table_name(&self, table_path: &[JsonPath]) -> String {
+        self.naming.table_name(&self.root_name, table_path)
+    }
+}
+
+impl Default for Identifiers {
+    fn default() -> Self {
+        Identifiers::new(
+            Arc::new(EscapedNaming),
+            Dialect::Generic,
+            String::from(crate::database::schema::DEFAULT_ROOT_NAME),
+        )
+    }
+}
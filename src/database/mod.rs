@@ -1,21 +1,107 @@
-use anyhow::Result;
-
+#[cfg(feature = "arrow")]
+pub use database_arrow::DatabaseArrow;
+pub use database_avro::DatabaseAvro;
 pub use database_csv::DatabaseCsv;
 pub use database_json::DatabaseJson;
+pub use database_jsonl::DatabaseJsonl;
+#[cfg(feature = "parquet")]
+pub use database_parquet::DatabaseParquet;
+#[cfg(feature = "postgres")]
+pub use database_postgres::DatabasePostgres;
+pub use database_sql::DatabaseSql;
+#[cfg(feature = "sqlite")]
+pub use database_sqlite::DatabaseSqlite;
 pub use database_stdout::DatabaseStdout;
-pub use schema::{ColumnSchema, DatabaseSchema, TableSchema};
+pub use database_tee::DatabaseTee;
+#[cfg(feature = "xlsx")]
+pub use database_xlsx::DatabaseXlsx;
+pub use formats::{format, formats, open_database, FormatOptions};
+pub use identifiers::Dialect;
+pub use schema::{
+    ColumnSchema, DatabaseSchema, KeyColumn, SchemaConfig, SourceColumn, TableSchema, ValueType,
+};
 
-use crate::parser::{TableLocation, TableRecord};
+use crate::parser::{Row, TableLocation, TableRecord};
+use crate::Error;
 
+#[cfg(feature = "arrow")]
+pub mod database_arrow;
+pub mod database_avro;
 pub mod database_csv;
 pub mod database_json;
+pub mod database_jsonl;
+#[cfg(feature = "parquet")]
+pub mod database_parquet;
+#[cfg(feature = "postgres")]
+pub mod database_postgres;
+pub mod database_sql;
+#[cfg(feature = "sqlite")]
+pub mod database_sqlite;
 pub mod database_stdout;
+pub mod database_tee;
+#[cfg(feature = "xlsx")]
+pub mod database_xlsx;
+pub mod ddl;
+pub mod formats;
+pub mod identifiers;
+pub mod naming;
+pub mod output;
 pub mod schema;
 
 /// Used as sink for records
 pub trait Database {
     fn get_schema(&self) -> &DatabaseSchema;
     fn get_schema_mut(&mut self) -> &mut DatabaseSchema;
-    fn write(&mut self, table: TableLocation, record: TableRecord) -> Result<()>;
-    fn close(&mut self) -> Result<()>;
+    fn write(&mut self, table: TableLocation, record: TableRecord) -> Result<(), Error>;
+
+    /// Write row borrowed from the parser, sinks that can use it without copying override this
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        self.write(row.location(), row.values.to_record())
+    }
+
+    fn close(&mut self) -> Result<(), Error>;
+}
+
+impl<D: Database + ?Sized> Database for &mut D {
+    fn get_schema(&self) -> &DatabaseSchema {
+        (**self).get_schema()
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        (**self).get_schema_mut()
+    }
+
+    fn write(&mut self, table: TableLocation, record: TableRecord) -> Result<(), Error> {
+        (**self).write(table, record)
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        (**self).write_row(row)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        (**self).close()
+    }
+}
+
+impl<D: Database + ?Sized> Database for Box<D> {
+    fn get_schema(&self) -> &DatabaseSchema {
+        (**self).get_schema()
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        (**self).get_schema_mut()
+    }
+
+    fn write(&mut self, table: TableLocation, record: TableRecord) -> Result<(), Error> {
+        (**self).write(table, record)
+    }
+
+    fn write_row(&mut self, row: &Row<'_>) -> Result<(), Error> {
+        (**self).write_row(row)
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        (**self).close()
+    }
 }
@@ -0,0 +1,360 @@
+use std::string::String;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use anyhow::{bail, Result};
+
+use crate::parser::JsonPath;
+
+/// Converts json paths to table and column names and back
+///
+/// Implementations must give distinct names to distinct paths,
+/// so that the `parse_*` methods can restore the path a name was made from.
+pub trait NamingStrategy: Send + Sync {
+    /// Name of column holding values found at `path` within table objects
+    fn column_name(&self, path: &JsonPath) -> String;
+
+    /// Restore path from name made by `column_name`
+    fn parse_column_name(&self, name: &str) -> Option<JsonPath>;
+
+    /// Name of table holding objects found at `table_path`
+    fn table_name(&self, root_name: &str, table_path: &[JsonPath]) -> String;
+
+    /// Restore table path from name made by `table_name`
+    fn parse_table_name(&self, root_name: &str, name: &str) -> Option<Vec<JsonPath>>;
+
+    /// Name of column holding ids of table at `table_path`, never clashes with `column_name`
+    fn key_column_name(&self, root_name: &str, table_path: &[JsonPath]) -> String;
+}
+
+/// Look up built-in naming strategy by name
+pub fn naming_strategy(name: &str) -> Option<Arc<dyn NamingStrategy>> {
+    match name {
+        "escaped" => Some(Arc::new(EscapedNaming)),
+        "dotted" => Some(Arc::new(SeparatorNaming::dotted())),
+        "underscored" => Some(Arc::new(SeparatorNaming::underscored())),
+        _ => None,
+    }
+}
+
+/// Check that `s` consists of at least `min` repetitions of `word`
+fn is_repetition(s: &str, word: &str, min: usize) -> bool {
+    let mut count = 0;
+    let mut rest = s;
+    while let Some(r) = rest.strip_prefix(word) {
+        rest = r;
+        count += 1;
+    }
+    rest.is_empty() && count >= min
+}
+
+/// Append `word` to every `_`-separated token made only of `word` repetitions,
+/// so that a bare `word` token can be used as separator
+fn escape_tokens(s: &str, word: &str) -> String {
+    s.split('_')
+        .map(|token| {
+            if is_repetition(token, word, 1) {
+                String::from(token) + word
+            } else {
+                String::from(token)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Reverse of `escape_tokens`
+fn unescape_tokens(s: &str, word: &str) -> String {
+    s.split('_')
+        .map(|token| {
+            if is_repetition(token, word, 2) {
+                &token[word.len()..]
+            } else {
+                token
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Split `s` by `_`-separated tokens equal to `word`
+fn split_tokens(s: &str, word: &str) -> Vec<String> {
+    let mut groups = vec![Vec::new()];
+    for token in s.split('_') {
+        if token == word {
+            groups.push(Vec::new());
+        } else {
+            groups.last_mut().unwrap().push(token);
+        }
+    }
+    groups.iter().map(|group| group.join("_")).collect()
+}
+
+/// Strip repetitions of `word` followed by `suffix` from start of `s`
+fn strip_repeated_prefix<'s>(s: &'s str, word: &str, suffix: &str) -> Option<(usize, &'s str)> {
+    let mut count = 0;
+    let mut rest = s;
+    while let Some(r) = rest.strip_prefix(word) {
+        rest = r;
+        count += 1;
+    }
+    rest.strip_prefix(suffix).map(|rest| (count, rest))
+}
+
+/// Names made of path elements in reverse order, separated with `_in_` for nested keys
+/// and `_lin_` for nested tables, e.g. `amount_in_price` or `authors_lin_books_lin_root`.
+///
+/// Elements that could be mistaken for separators or reserved names are escaped
+/// by repeating a word: `_in_` becomes `_inin_`, `list` becomes `listlist`,
+/// leading `_` becomes `tech_` and leading `id_` becomes `idid_`.
+#[derive(Default, Clone, Copy)]
+pub struct EscapedNaming;
+
+impl EscapedNaming {
+    fn escape_key(key: &str) -> String {
+        if key.is_empty() {
+            return String::from("empty");
+        }
+
+        let mut s = escape_tokens(key, "in");
+        if is_repetition(&s, "empty", 1) {
+            s += "empty";
+        }
+        if is_repetition(&s, "list", 1) {
+            s += "list";
+        }
+        // Leading underscore would merge with separator
+        if strip_repeated_prefix(&s, "tech", "_").is_some() {
+            s = String::from("tech") + &s;
+        }
+        s
+    }
+
+    fn unescape_key(s: &str) -> String {
+        if s == "empty" {
+            return String::new();
+        }
+
+        let s = match strip_repeated_prefix(s, "tech", "_") {
+            Some((count, _)) if count > 0 => &s["tech".len()..],
+            _ => s,
+        };
+        let s = if is_repetition(s, "list", 2) || is_repetition(s, "empty", 2) {
+            let word_len = if s.starts_with("list") { 4 } else { 5 };
+            &s[word_len..]
+        } else {
+            s
+        };
+        unescape_tokens(s, "in")
+    }
+
+    fn unescape_id_prefix(s: &str) -> &str {
+        match strip_repeated_prefix(s, "id", "_") {
+            Some((count, _)) if count > 1 => &s["id".len()..],
+            _ => s,
+        }
+    }
+}
+
+impl NamingStrategy for EscapedNaming {
+    fn column_name(&self, path: &JsonPath) -> String {
+        let key = if path.is_empty() {
+            String::from("list")
+        } else {
+            path.iter()
+                .rev()
+                .map(|k| EscapedNaming::escape_key(k))
+                .collect::<Vec<String>>()
+                .join("_in_")
+        };
+        // Names starting with `id_` are reserved for key columns
+        if strip_repeated_prefix(&key, "id", "_").is_some_and(|(count, _)| count > 0) {
+            String::from("id") + &key
+        } else {
+            key
+        }
+    }
+
+    fn parse_column_name(&self, name: &str) -> Option<JsonPath> {
+        let key = EscapedNaming::unescape_id_prefix(name);
+        let path = if key == "list" {
+            JsonPath::new()
+        } else {
+            split_tokens(key, "in")
+                .iter()
+                .rev()
+                .map(|k| EscapedNaming::unescape_key(k))
+                .collect()
+        };
+        (self.column_name(&path) == name).then_some(path)
+    }
+
+    fn table_name(&self, root_name: &str, table_path: &[JsonPath]) -> String {
+        table_path
+            .iter()
+            .rev()
+            .chain(std::iter::once(&vec![root_name.to_string()]))
+            .map(|p| escape_tokens(&self.column_name(p), "lin"))
+            .collect::<Vec<String>>()
+            .join("_lin_")
+    }
+
+    fn parse_table_name(&self, root_name: &str, name: &str) -> Option<Vec<JsonPath>> {
+        let mut table_path = split_tokens(name, "lin")
+            .iter()
+            .map(|p| self.parse_column_name(&unescape_tokens(p, "lin")))
+            .collect::<Option<Vec<JsonPath>>>()?;
+        if table_path.pop()? != vec![root_name.to_string()] {
+            return None;
+        }
+        table_path.reverse();
+        (self.table_name(root_name, &table_path) == name).then_some(table_path)
+    }
+
+    fn key_column_name(&self, root_name: &str, table_path: &[JsonPath]) -> String {
+        String::from("id_") + &self.table_name(root_name, table_path)
+    }
+}
+
+/// Character escaping separators within names of `SeparatorNaming`
+const ESCAPE: char = '\\';
+
+/// Column name of values stored directly in list
+const EMPTY_PATH: &str = "$";
+
+/// Names made of path elements in document order joined with separators,
+/// e.g. `price.amount` for columns and `root-books-authors` for tables.
+///
+/// Separators occurring within keys are escaped with a backslash.
+#[derive(Clone)]
+pub struct SeparatorNaming {
+    separator: String,
+    table_separator: String,
+}
+
+impl SeparatorNaming {
+    pub fn new(separator: &str, table_separator: &str) -> Result<SeparatorNaming> {
+        if separator.is_empty() || table_separator.is_empty() {
+            bail!("Separators must not be empty");
+        }
+        if separator == table_separator {
+            bail!("Column and table separators must differ");
+        }
+        if separator.contains(ESCAPE) || table_separator.contains(ESCAPE) {
+            bail!("Separators must not contain {}", ESCAPE);
+        }
+        Ok(SeparatorNaming {
+            separator: separator.to_string(),
+            table_separator: table_separator.to_string(),
+        })
+    }
+
+    /// Keys joined with `.`, tables joined with `-`
+    pub fn dotted() -> SeparatorNaming {
+        SeparatorNaming::new(".", "-").unwrap()
+    }
+
+    /// Keys joined with `__`, tables joined with `___`
+    ///
+    /// Keys themselves are kept as they are, `firstName` stays `firstName`. Converting
+    /// them to snake_case was left out on purpose: `firstName` and `first_name` would
+    /// both become `first_name`, so names could no longer be parsed back into paths
+    /// as `NamingStrategy` requires.
+    pub fn underscored() -> SeparatorNaming {
+        SeparatorNaming::new("__", "___").unwrap()
+    }
+
+    /// Escape characters that start a separator or could start one when joined
+    fn escape(s: &str, separator: &str) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        for (i, c) in s.char_indices() {
+            let rest = &s[i..];
+            if c == ESCAPE || rest.starts_with(separator) || separator.starts_with(rest) {
+                escaped.push(ESCAPE);
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    /// Split by unescaped separators, removing escapes
+    fn split(s: &str, separator: &str) -> Option<Vec<String>> {
+        let mut parts = vec![String::new()];
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c == ESCAPE {
+                parts.last_mut().unwrap().push(chars.next()?.1);
+            } else if s[i..].starts_with(separator) {
+                parts.push(String::new());
+                // Skip the rest of separator
+                for _ in 1..separator.chars().count() {
+                    chars.next();
+                }
+            } else {
+                parts.last_mut().unwrap().push(c);
+            }
+        }
+        Some(parts)
+    }
+
+    fn key_prefix(&self) -> String {
+        String::from("id") + &self.separator
+    }
+}
+
+impl NamingStrategy for SeparatorNaming {
+    fn column_name(&self, path: &JsonPath) -> String {
+        if path.is_empty() {
+            return String::from(EMPTY_PATH);
+        }
+        let mut name = path
+            .iter()
+            .map(|k| SeparatorNaming::escape(k, &self.separator))
+            .collect::<Vec<String>>()
+            .join(&self.separator);
+        if name.starts_with(EMPTY_PATH) {
+            name.insert(0, ESCAPE);
+        }
+        // Names starting with key prefix are reserved for key columns
+        if strip_repeated_prefix(&name, "id", &self.separator).is_some_and(|(count, _)| count > 0) {
+            name = String::from("id") + &name;
+        }
+        name
+    }
+
+    fn parse_column_name(&self, name: &str) -> Option<JsonPath> {
+        let path = if name == EMPTY_PATH {
+            JsonPath::new()
+        } else {
+            let key = match strip_repeated_prefix(name, "id", &self.separator) {
+                Some((count, _)) if count > 1 => &name["id".len()..],
+                _ => name,
+            };
+            SeparatorNaming::split(key, &self.separator)?
+        };
+        (self.column_name(&path) == name).then_some(path)
+    }
+
+    fn table_name(&self, root_name: &str, table_path: &[JsonPath]) -> String {
+        std::iter::once(&vec![root_name.to_string()])
+            .chain(table_path.iter())
+            .map(|p| SeparatorNaming::escape(&self.column_name(p), &self.table_separator))
+            .collect::<Vec<String>>()
+            .join(&self.table_separator)
+    }
+
+    fn parse_table_name(&self, root_name: &str, name: &str) -> Option<Vec<JsonPath>> {
+        let mut table_path = SeparatorNaming::split(name, &self.table_separator)?
+            .iter()
+            .map(|p| self.parse_column_name(p))
+            .collect::<Option<Vec<JsonPath>>>()?;
+        if table_path.is_empty() || table_path.remove(0) != vec![root_name.to_string()] {
+            return None;
+        }
+        (self.table_name(root_name, &table_path) == name).then_some(table_path)
+    }
+
+    fn key_column_name(&self, root_name: &str, table_path: &[JsonPath]) -> String {
+        self.key_prefix() + &self.table_name(root_name, table_path)
+    }
+}
@@ -0,0 +1,127 @@
+use std::borrow::Cow;
+use std::fs::{create_dir_all, remove_file, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::database::{ColumnSchema, DatabaseSchema, TableSchema};
+use crate::parser::Row;
+
+/// Create output directory, refusing to mix output with files of other runs
+pub fn ensure_dir_exists_and_empty(path: &PathBuf) -> Result<()> {
+    create_dir_all(path).with_context(|| {
+        format!(
+            "Could not ensure directory {} exists",
+            path.to_string_lossy()
+        )
+    })?;
+    let is_empty = path
+        .read_dir()
+        .with_context(|| {
+            format!(
+                "Could not read directory {} to check if it is empty",
+                path.to_string_lossy()
+            )
+        })?
+        .next()
+        .is_none();
+    if is_empty {
+        Ok(())
+    } else {
+        Err(anyhow!("Directory {} is not empty", path.to_string_lossy()))
+    }
+}
+
+/// Save schema to `schema.json` in output directory
+pub fn write_schema(path: &Path, schema: &DatabaseSchema) -> Result<()> {
+    let schema_path = path.join("schema.json");
+    let schema_file = File::create(&schema_path)
+        .with_context(|| format!("Could not create file {}", schema_path.to_string_lossy()))?;
+    serde_json::to_writer_pretty(BufWriter::new(schema_file), schema)
+        .context("Could not write schema")
+}
+
+/// Values of row in the column order of the schema, borrowed from the row where
+/// `cells` set by `TableSchema::update_values` locate them, missing values are null
+pub fn row_values<'r>(
+    schema: &TableSchema,
+    row: &Row<'r>,
+    cells: &[Option<usize>],
+) -> Vec<Cow<'r, Value>> {
+    schema
+        .columns
+        .iter()
+        .zip(cells)
+        .map(|(col, cell)| match col {
+            ColumnSchema::SourceColumn(_) => match cell.and_then(|i| row.values.get_index(i)) {
+                Some((_, v)) => Cow::Borrowed(v),
+                None => Cow::Owned(Value::Null),
+            },
+            ColumnSchema::PrimaryKey(_) => Cow::Owned(Value::from(row.object_id)),
+            ColumnSchema::ForeignKey(_) => Cow::Owned(Value::from(row.parent_object_id)),
+        })
+        .collect()
+}
+
+/// Temporary file keeping rows of a table until its final set of columns is known
+///
+/// Rows are stored as json arrays, one per line. Rows written before
+/// a column was added are shorter and get padded with nulls on reading.
+pub struct RowSpool {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    rows: usize,
+}
+
+impl RowSpool {
+    /// Create spool next to `data_path`
+    pub fn new(data_path: &Path) -> Result<RowSpool> {
+        let mut path = data_path.to_path_buf().into_os_string();
+        path.push(".part");
+        let path = PathBuf::from(path);
+        let file = File::create(&path)
+            .with_context(|| format!("Could not create file {}", path.to_string_lossy()))?;
+        Ok(RowSpool {
+            writer: BufWriter::new(file),
+            path,
+            rows: 0,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn push<V: Serialize>(&mut self, row: &[V]) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, row).context("Could not write to file")?;
+        self.writer
+            .write_all(b"\n")
+            .context("Could not write to file")?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Read spooled rows back, each padded to `width` values
+    pub fn read(&mut self, width: usize) -> Result<impl Iterator<Item = Result<Vec<Value>>>> {
+        self.writer.flush().context("Could not flush table")?;
+        let file = File::open(&self.path)
+            .with_context(|| format!("Could not read file {}", self.path.to_string_lossy()))?;
+        Ok(BufReader::new(file).lines().map(move |line| {
+            let mut row: Vec<Value> =
+                serde_json::from_str(&line.context("Could not read spooled row")?)
+                    .context("Could not parse spooled row")?;
+            row.resize(width, Value::Null);
+            Ok(row)
+        }))
+    }
+
+    /// Delete temporary file
+    pub fn remove(self) -> Result<()> {
+        drop(self.writer);
+        remove_file(&self.path)
+            .with_context(|| format!("Could not remove file {}", self.path.to_string_lossy()))
+    }
+}
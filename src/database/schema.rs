@@ -1,140 +1,591 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read};
 use std::mem::swap;
+use std::path::Path;
 use std::string::String;
+use std::sync::Arc;
 
-use serde::{Deserialize, Deserializer, Serialize};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::parser::{JsonPath, TableRecord};
+use crate::database::identifiers::{Dialect, Identifiers};
+use crate::database::naming::{EscapedNaming, NamingStrategy};
+use crate::parser::{JsonPath, RowValues, TableRecord};
+use crate::Error;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(from = "SourceColumnData")]
 pub struct SourceColumn {
     pub source_path: JsonPath,
+    // Identifier of the column in target database
+    pub name: String,
+    // Column had null value or was missing in some record
     pub is_nullable: bool,
     pub is_null: bool,
     pub is_bool: bool,
     pub is_i64: bool,
     pub is_f64: bool,
+    pub is_number: bool,
     pub example_values: Vec<Value>,
 }
 
-#[derive(Deserialize, Serialize)]
+/// Serialized form of `SourceColumn`, schemas saved before `is_number` existed lack it
+#[derive(Deserialize)]
+struct SourceColumnData {
+    source_path: JsonPath,
+    name: String,
+    is_nullable: bool,
+    is_null: bool,
+    is_bool: bool,
+    is_i64: bool,
+    is_f64: bool,
+    #[serde(default)]
+    is_number: Option<bool>,
+    example_values: Vec<Value>,
+}
+
+impl From<SourceColumnData> for SourceColumn {
+    fn from(data: SourceColumnData) -> Self {
+        SourceColumn {
+            is_number: data.is_number.unwrap_or(data.is_i64 || data.is_f64),
+            source_path: data.source_path,
+            name: data.name,
+            is_nullable: data.is_nullable,
+            is_null: data.is_null,
+            is_bool: data.is_bool,
+            is_i64: data.is_i64,
+            is_f64: data.is_f64,
+            example_values: data.example_values,
+        }
+    }
+}
+
+/// Type of all non-null values of a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// Column holds only nulls
+    Null,
+    Bool,
+    Integer,
+    Float,
+    /// Strings, or values of mixed types rendered as strings
+    String,
+}
+
+impl SourceColumn {
+    pub fn value_type(&self) -> ValueType {
+        if self.is_null {
+            ValueType::Null
+        } else if self.is_bool {
+            ValueType::Bool
+        } else if self.is_i64 {
+            ValueType::Integer
+        } else if self.is_number {
+            ValueType::Float
+        } else {
+            ValueType::String
+        }
+    }
+}
+
+/// Column holding object ids, of the table itself or of its parent
+#[derive(Clone, Deserialize, Serialize)]
+pub struct KeyColumn {
+    // Identifier of the column in target database
+    pub name: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub enum ColumnSchema {
     SourceColumn(SourceColumn),
-    PrimaryKey,
-    ForeignKey,
+    PrimaryKey(KeyColumn),
+    ForeignKey(KeyColumn),
 }
 
-#[derive(Deserialize, Serialize)]
+impl ColumnSchema {
+    /// Identifier of the column in target database
+    pub fn name(&self) -> &str {
+        match self {
+            ColumnSchema::SourceColumn(col) => &col.name,
+            ColumnSchema::PrimaryKey(col) => &col.name,
+            ColumnSchema::ForeignKey(col) => &col.name,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(from = "TableSchemaData")]
 pub struct TableSchema {
-    #[serde(skip_deserializing,skip_serializing)]
+    #[serde(skip_serializing)]
     path_to_id: HashMap<JsonPath, usize>,
+    // Column identifiers in use, folded according to dialect
+    #[serde(skip_serializing)]
+    column_ids: HashSet<String>,
+    #[serde(skip_serializing)]
+    identifiers: Arc<Identifiers>,
     // Ordered mapping of json paths to string
     pub columns: Vec<ColumnSchema>,
     pub name: String,
     pub path: Vec<JsonPath>,
+    // Number of records seen
+    pub records: usize,
 }
 
+/// Serialized form of `TableSchema`, without lookup maps
+#[derive(Deserialize)]
+struct TableSchemaData {
+    columns: Vec<ColumnSchema>,
+    name: String,
+    path: Vec<JsonPath>,
+    #[serde(default)]
+    records: usize,
+}
+
+impl From<TableSchemaData> for TableSchema {
+    fn from(data: TableSchemaData) -> Self {
+        let path_to_id = data
+            .columns
+            .iter()
+            .enumerate()
+            .filter_map(|(col_id, col)| match col {
+                ColumnSchema::SourceColumn(col) => Some((col.source_path.clone(), col_id)),
+                ColumnSchema::PrimaryKey(_) => None,
+                ColumnSchema::ForeignKey(_) => None,
+            })
+            .collect();
+        let mut schema = TableSchema {
+            path_to_id,
+            column_ids: HashSet::new(),
+            identifiers: Arc::new(Identifiers::default()),
+            columns: data.columns,
+            name: data.name,
+            path: data.path,
+            records: data.records,
+        };
+        schema.set_identifiers(schema.identifiers.clone());
+        schema
+    }
+}
 
 impl TableSchema {
-    pub fn empty_with_ids(name: String, path: Vec<JsonPath>) -> TableSchema {
+    pub fn empty_with_ids(
+        name: String,
+        path: Vec<JsonPath>,
+        identifiers: Arc<Identifiers>,
+    ) -> TableSchema {
         let mut schema = TableSchema {
             path_to_id: HashMap::new(),
+            column_ids: HashSet::new(),
+            identifiers,
             columns: Vec::new(),
             name,
             path,
+            records: 0,
         };
-        schema.add_column(ColumnSchema::PrimaryKey);
-        schema.add_column(ColumnSchema::ForeignKey);
+        let name = schema.new_column_id(&schema.identifiers.key_column_name(&schema.path));
+        schema.add_column(ColumnSchema::PrimaryKey(KeyColumn { name }));
+        // Root table has no parent to refer to
+        if !schema.path.is_empty() {
+            let mut parent_path = schema.path.clone();
+            parent_path.pop();
+            let name = schema.new_column_id(&schema.identifiers.key_column_name(&parent_path));
+            schema.add_column(ColumnSchema::ForeignKey(KeyColumn { name }));
+        }
 
         schema
     }
 
+    /// Use `identifiers` to name columns added from now on
+    pub fn set_identifiers(&mut self, identifiers: Arc<Identifiers>) {
+        let dialect = identifiers.dialect();
+        self.column_ids = self
+            .columns
+            .iter()
+            .map(|col| dialect.fold(col.name()))
+            .collect();
+        self.identifiers = identifiers;
+    }
+
+    /// Make identifier for column name, unique within the table
+    fn new_column_id(&mut self, name: &str) -> String {
+        let dialect = self.identifiers.dialect();
+        let id = dialect.unique_identifier(name, &self.column_ids);
+        self.column_ids.insert(dialect.fold(&id));
+        id
+    }
+
     pub fn add_column(&mut self, col: ColumnSchema) {
         self.columns.push(col);
     }
 
+    /// Column holding ids of table objects
+    pub fn primary_key(&self) -> Option<&KeyColumn> {
+        self.columns.iter().find_map(|col| match col {
+            ColumnSchema::PrimaryKey(key) => Some(key),
+            _ => None,
+        })
+    }
+
+    /// Column holding ids of parent objects, root table has none
+    pub fn foreign_key(&self) -> Option<&KeyColumn> {
+        self.columns.iter().find_map(|col| match col {
+            ColumnSchema::ForeignKey(key) => Some(key),
+            _ => None,
+        })
+    }
+
+    /// Find column holding values at `path`
+    pub fn source_column(&self, path: &JsonPath) -> Option<&SourceColumn> {
+        match self
+            .path_to_id
+            .get(path)
+            .map(|col_id| &self.columns[*col_id])
+        {
+            Some(ColumnSchema::SourceColumn(col)) => Some(col),
+            _ => None,
+        }
+    }
+
+    /// Update column statistics with record values
     pub fn update(&mut self, rec: &TableRecord) {
-        for (k, v) in rec.iter() {
-            let col_id = self.path_to_id.get(k);
-            let col_id = if col_id.is_none() {
-                self.add_column(ColumnSchema::SourceColumn(SourceColumn {
-                    source_path: k.clone(),
-                    is_nullable: false,
-                    is_null: true,
-                    is_bool: true,
-                    is_i64: true,
-                    is_f64: true,
-                    example_values: Vec::new(),
-                }));
-                self.path_to_id.insert(k.clone(), self.columns.len() - 1);
-                self.columns.len() - 1
-            } else {
-                *col_id.unwrap()
+        self.update_values(&RowValues::Record(rec), &mut Vec::new());
+    }
+
+    /// Update column statistics with row values, setting `cells` to the position
+    /// of the value of each column within the row
+    ///
+    /// New columns are appended in the order they first appear in the document,
+    /// so the same input always yields the same column order
+    pub fn update_values(&mut self, values: &RowValues<'_>, cells: &mut Vec<Option<usize>>) {
+        cells.clear();
+        cells.resize(self.columns.len(), None);
+        for (position, (k, v)) in values.iter().enumerate() {
+            let col_id = match self.path_to_id.get(k) {
+                Some(col_id) => *col_id,
+                None => {
+                    let name = self.new_column_id(&self.identifiers.column_name(k));
+                    self.add_column(ColumnSchema::SourceColumn(SourceColumn {
+                        source_path: k.clone(),
+                        name,
+                        // Column is missing in records seen before
+                        is_nullable: self.records > 0,
+                        is_null: true,
+                        is_bool: true,
+                        is_i64: true,
+                        is_f64: true,
+                        is_number: true,
+                        example_values: Vec::new(),
+                    }));
+                    self.path_to_id.insert(k.clone(), self.columns.len() - 1);
+                    cells.push(None);
+                    self.columns.len() - 1
+                }
             };
+            cells[col_id] = Some(position);
             // Update column status with value
             let col = &mut self.columns[col_id];
             match col {
                 ColumnSchema::SourceColumn(ref mut _col) => {
                     _col.is_nullable = _col.is_nullable || v.is_null();
                     _col.is_null = _col.is_null && v.is_null();
-                    _col.is_bool = _col.is_bool && v.is_boolean();
-                    _col.is_i64 = _col.is_i64 && v.is_i64();
-                    _col.is_f64 = _col.is_f64 && v.is_f64();
+                    // Nulls do not affect the type of a column
+                    _col.is_bool = _col.is_bool && (v.is_null() || v.is_boolean());
+                    _col.is_i64 = _col.is_i64 && (v.is_null() || v.is_i64());
+                    _col.is_f64 = _col.is_f64 && (v.is_null() || v.is_f64());
+                    _col.is_number = _col.is_number && (v.is_null() || v.is_number());
 
                     if _col.example_values.len() < 5 && !v.is_null() {
                         _col.example_values.push(v.clone());
                     }
                 }
-                ColumnSchema::PrimaryKey => {}
-                ColumnSchema::ForeignKey => {}
+                ColumnSchema::PrimaryKey(_) => {}
+                ColumnSchema::ForeignKey(_) => {}
+            }
+        }
+
+        // Columns missing in the record hold nulls in its row
+        if values.len() < self.path_to_id.len() {
+            for (col, cell) in self.columns.iter_mut().zip(cells.iter()) {
+                if let ColumnSchema::SourceColumn(col) = col {
+                    col.is_nullable = col.is_nullable || cell.is_none();
+                }
             }
         }
+        self.records += 1;
+    }
+}
+
+/// Default name of the root table
+pub const DEFAULT_ROOT_NAME: &str = "root";
+
+fn default_root_name() -> String {
+    String::from(DEFAULT_ROOT_NAME)
+}
+
+/// Table name supplied by user for specific table path
+#[derive(Deserialize, Serialize)]
+pub struct TableNameOverride {
+    pub path: Vec<JsonPath>,
+    pub name: String,
+}
+
+/// Naming settings read from config file
+///
+/// Example:
+/// ```json
+/// {
+///     "root_name": "bookstore",
+///     "table_names": [{"path": [["books"], ["authors"]], "name": "authors"}]
+/// }
+/// ```
+#[derive(Deserialize, Serialize, Default)]
+pub struct SchemaConfig {
+    #[serde(default)]
+    pub root_name: Option<String>,
+    #[serde(default)]
+    pub table_names: Vec<TableNameOverride>,
+}
+
+impl SchemaConfig {
+    /// Read config from json file
+    pub fn load_file(path: &Path) -> Result<SchemaConfig, Error> {
+        let file = File::open(path).map_err(|e| {
+            Error::Io(e.into()).context(format!(
+                "Could not open config file {}",
+                path.to_string_lossy()
+            ))
+        })?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+            Error::Schema(e.into()).context(format!(
+                "Could not parse config file {}",
+                path.to_string_lossy()
+            ))
+        })
     }
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(try_from = "DatabaseSchemaData")]
 pub struct DatabaseSchema {
-    #[serde(skip_deserializing,skip_serializing)]
+    #[serde(skip_serializing)]
     table_path_to_id: HashMap<Vec<JsonPath>, usize>,
+    // Names of all known tables, kept to ensure new names are unique
+    #[serde(skip_serializing)]
+    table_names: HashSet<String>,
+    // User supplied names for tables that were not created yet
+    #[serde(skip_serializing)]
+    name_overrides: HashMap<Vec<JsonPath>, String>,
+    // Converts json paths to table and column names
+    #[serde(skip_serializing)]
+    naming: Arc<dyn NamingStrategy>,
+    // Naming combined with dialect rules, shared with borrowed tables
+    #[serde(skip_serializing)]
+    identifiers: Arc<Identifiers>,
+    // Name of the root table, other table names are derived from it
+    root_name: String,
+    // Database whose identifier rules table and column names follow
+    dialect: Dialect,
     // When table schema is borrowed for serializing, the value will be None
     tables: Vec<Option<TableSchema>>,
 }
 
-impl<'de> Deserialize<'de> for DatabaseSchema {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-        where
-            D: Deserializer<'de>,
-    {
-        let db: DatabaseSchema = Deserialize::deserialize(deserializer)?;
-        Ok(db)
-    }
+/// Serialized form of `DatabaseSchema`, without lookup maps
+///
+/// Schema can only be serialized when all tables are returned
+#[derive(Deserialize)]
+struct DatabaseSchemaData {
+    #[serde(default = "default_root_name")]
+    root_name: String,
+    #[serde(default)]
+    dialect: Dialect,
+    tables: Vec<TableSchema>,
 }
 
+impl TryFrom<DatabaseSchemaData> for DatabaseSchema {
+    type Error = String;
+
+    fn try_from(data: DatabaseSchemaData) -> std::result::Result<Self, Self::Error> {
+        let mut schema = DatabaseSchema::new(data.root_name);
+        schema.set_dialect(data.dialect);
+        for table in data.tables {
+            if schema.table_path_to_id.contains_key(&table.path) {
+                return Err(format!("Duplicate table path {:?} in schema", table.path));
+            }
+            if !schema.table_names.insert(table.name.clone()) {
+                return Err(format!("Duplicate table name {} in schema", table.name));
+            }
+            schema
+                .table_path_to_id
+                .insert(table.path.clone(), schema.tables.len());
+            schema.tables.push(Some(table));
+        }
+        Ok(schema)
+    }
+}
 
 impl DatabaseSchema {
-    pub fn empty() -> DatabaseSchema {
+    pub fn new(root_name: String) -> DatabaseSchema {
         DatabaseSchema {
             table_path_to_id: HashMap::new(),
+            table_names: HashSet::new(),
+            name_overrides: HashMap::new(),
+            naming: Arc::new(EscapedNaming),
+            identifiers: Arc::new(Identifiers::new(
+                Arc::new(EscapedNaming),
+                Dialect::Generic,
+                root_name.clone(),
+            )),
+            root_name,
+            dialect: Dialect::Generic,
             tables: Vec::new(),
         }
     }
 
+    pub fn empty() -> DatabaseSchema {
+        DatabaseSchema::new(default_root_name())
+    }
+
+    /// Apply naming settings to tables created from now on
+    ///
+    /// Tables that are already known keep their names
+    pub fn configure(&mut self, config: SchemaConfig) -> Result<(), Error> {
+        if let Some(root_name) = config.root_name {
+            self.root_name = root_name;
+            self.rebuild_identifiers();
+        }
+        // Overrides get exactly the configured names, so they can't take names in use
+        let known: HashMap<String, Vec<JsonPath>> = self
+            .tables()
+            .map(|table| (self.dialect.fold(&table.name), table.path.clone()))
+            .collect();
+        let mut override_names = HashSet::new();
+        for TableNameOverride { path, name } in config.table_names {
+            let folded = self.dialect.fold(&name);
+            if !override_names.insert(folded.clone()) {
+                let error = anyhow!("Table name {} is configured more than once", name);
+                return Err(Error::Schema(error));
+            }
+            if known
+                .get(&folded)
+                .is_some_and(|known_path| *known_path != path)
+            {
+                let error = anyhow!("Table name {} is already used by another table", name);
+                return Err(Error::Schema(error));
+            }
+            self.name_overrides.insert(path, name);
+        }
+        Ok(())
+    }
+
+    pub fn root_name(&self) -> &str {
+        &self.root_name
+    }
+
+    /// Use `naming` for tables and columns created from now on
+    pub fn set_naming(&mut self, naming: Arc<dyn NamingStrategy>) {
+        self.naming = naming;
+        self.rebuild_identifiers();
+    }
+
+    pub fn naming(&self) -> &dyn NamingStrategy {
+        self.naming.as_ref()
+    }
+
+    /// Follow identifier rules of `dialect` for tables and columns created from now on
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+        self.rebuild_identifiers();
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub fn identifiers(&self) -> &Identifiers {
+        &self.identifiers
+    }
+
+    fn rebuild_identifiers(&mut self) {
+        self.identifiers = Arc::new(Identifiers::new(
+            self.naming.clone(),
+            self.dialect,
+            self.root_name.clone(),
+        ));
+    }
+
+    /// Read schema previously saved to json, keeping its table names and column positions
+    pub fn load<R: Read>(reader: R) -> Result<DatabaseSchema, Error> {
+        serde_json::from_reader(reader)
+            .map_err(|e| Error::Schema(e.into()).context("Could not parse database schema"))
+    }
+
+    /// Read schema from json file, see `DatabaseSchema::load`
+    pub fn load_file(path: &Path) -> Result<DatabaseSchema, Error> {
+        let file = File::open(path).map_err(|e| {
+            Error::Io(e.into()).context(format!(
+                "Could not open schema file {}",
+                path.to_string_lossy()
+            ))
+        })?;
+        DatabaseSchema::load(BufReader::new(file)).map_err(|e| {
+            e.context(format!(
+                "Could not load schema file {}",
+                path.to_string_lossy()
+            ))
+        })
+    }
+
+    /// Iterate over all tables that are not currently borrowed
+    pub fn tables(&self) -> impl Iterator<Item = &TableSchema> {
+        self.tables.iter().flatten()
+    }
+
+    /// Find table that is not currently borrowed by its path
+    pub fn table(&self, path: &[JsonPath]) -> Option<&TableSchema> {
+        self.table_path_to_id
+            .get(path)
+            .and_then(|t_id| self.tables[*t_id].as_ref())
+    }
+
+    /// Add table of `path` unless known, so that it gets its name now
+    ///
+    /// Tables are named in the order they are added, as a name clashing with
+    /// an earlier one gets a suffix.
+    pub fn add_table(&mut self, path: &[JsonPath]) {
+        if self.table_path_to_id.contains_key(path) {
+            return;
+        }
+        let schema = TableSchema::empty_with_ids(
+            self.new_table_name(path),
+            path.to_vec(),
+            self.identifiers.clone(),
+        );
+        self.table_path_to_id
+            .insert(path.to_vec(), self.tables.len());
+        self.tables.push(Some(schema));
+    }
+
     /// Get unique table name for specified json path
-    pub fn borrow_table_schema(&mut self, path: &Vec<JsonPath>) -> Option<TableSchema> {
+    pub fn borrow_table_schema(&mut self, path: &[JsonPath]) -> Option<TableSchema> {
         let table_id = self.table_path_to_id.get(path);
         match table_id {
             Some(t_id) => {
                 let mut schema: Option<TableSchema> = None;
                 swap(self.tables.get_mut(*t_id).unwrap(), &mut schema);
-                schema
+                schema.map(|mut schema| {
+                    schema.set_identifiers(self.identifiers.clone());
+                    schema
+                })
             }
             None => {
                 let table_id = self.tables.len();
-                self.table_path_to_id.insert(path.clone(), table_id);
+                self.table_path_to_id.insert(path.to_vec(), table_id);
                 let schema = TableSchema::empty_with_ids(
-                    String::from("table_") + &table_id.to_string(),
-                    path.clone(),
+                    self.new_table_name(path),
+                    path.to_vec(),
+                    self.identifiers.clone(),
                 );
                 self.tables.push(None);
                 Some(schema)
@@ -142,22 +593,51 @@ impl DatabaseSchema {
         }
     }
 
-    pub fn return_table_schema(&mut self, path: &Vec<JsonPath>, schema: TableSchema) {
-        let table_id = self
+    /// Derive unique name for a new table from its path, unless overridden by config
+    fn new_table_name(&mut self, path: &[JsonPath]) -> String {
+        let name = match self.name_overrides.get(path) {
+            Some(name) => name.clone(),
+            None => self.identifiers.table_name(path),
+        };
+
+        // Derived names are unique, but may clash after applying dialect rules
+        // or with overridden ones, which are kept for their tables
+        let reserved = self
+            .name_overrides
+            .iter()
+            .filter(|(override_path, _)| override_path.as_slice() != path)
+            .map(|(_, name)| name);
+        let used = self
+            .table_names
+            .iter()
+            .chain(reserved)
+            .map(|name| self.dialect.fold(name))
+            .collect();
+        let unique_name = self.dialect.unique_identifier(&name, &used);
+        self.table_names.insert(unique_name.clone());
+        unique_name
+    }
+
+    pub fn return_table_schema(
+        &mut self,
+        path: &Vec<JsonPath>,
+        schema: TableSchema,
+    ) -> Result<(), Error> {
+        let table = self
             .table_path_to_id
             .get(path)
-            .expect("Returned table that was not borrowed");
-        swap(self.tables.get_mut(*table_id).unwrap(), &mut Some(schema));
+            .and_then(|table_id| self.tables.get_mut(*table_id))
+            .ok_or_else(|| {
+                Error::Schema(anyhow!("Returned table {} was not borrowed", schema.name))
+            })?;
+        *table = Some(schema);
+        Ok(())
     }
 
-    pub fn ensure_all_tables_returned(&self) {
-        for t in self.tables.iter() {
-            match t {
-                None => {
-                    panic!("Table was not returned!")
-                }
-                Some(_) => {}
-            }
+    pub fn ensure_all_tables_returned(&self) -> Result<(), Error> {
+        if self.tables.iter().any(Option::is_none) {
+            return Err(Error::Schema(anyhow!("Table schema was not returned")));
         }
+        Ok(())
     }
 }
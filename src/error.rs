@@ -0,0 +1,99 @@
+use std::fmt;
+use std::io;
+
+use crate::parser::Location;
+use crate::yajlish::ParseError;
+
+/// Error of converting json to tables, keeping the whole chain of its causes
+#[derive(Debug)]
+pub enum Error {
+    /// Input is not valid json
+    Parse(anyhow::Error),
+    /// Input could not be read, or output could not be written
+    Io(anyhow::Error),
+    /// Schema could not be loaded, or does not fit records
+    Schema(anyhow::Error),
+    /// Sink, or callback, failed to take records
+    Sink(anyhow::Error),
+}
+
+impl Error {
+    /// Error with its causes
+    pub fn inner(&self) -> &anyhow::Error {
+        match self {
+            Error::Parse(e) | Error::Io(e) | Error::Schema(e) | Error::Sink(e) => e,
+        }
+    }
+
+    /// Wrap error with `context`, keeping its kind
+    pub fn context<C>(self, context: C) -> Error
+    where
+        C: fmt::Display + Send + Sync + 'static,
+    {
+        match self {
+            Error::Parse(e) => Error::Parse(e.context(context)),
+            Error::Io(e) => Error::Io(e.context(context)),
+            Error::Schema(e) => Error::Schema(e.context(context)),
+            Error::Sink(e) => Error::Sink(e.context(context)),
+        }
+    }
+
+    /// Where in the input the error happened, if known
+    pub fn location(&self) -> Option<&Location> {
+        self.inner().downcast_ref::<Location>()
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    /// Classify error by the first of its causes telling the kind
+    fn from(e: anyhow::Error) -> Self {
+        // Error passed on through anyhow without further context is taken back as it was
+        if e.chain().next().is_some_and(|cause| cause.is::<Error>()) {
+            return e.downcast::<Error>().expect("outermost cause is Error");
+        }
+        enum Kind {
+            Parse,
+            Io,
+            Schema,
+            Sink,
+        }
+        let kind = e.chain().find_map(|cause| {
+            if let Some(error) = cause.downcast_ref::<Error>() {
+                return Some(match error {
+                    Error::Parse(_) => Kind::Parse,
+                    Error::Io(_) => Kind::Io,
+                    Error::Schema(_) => Kind::Schema,
+                    Error::Sink(_) => Kind::Sink,
+                });
+            }
+            if let Some(error) = cause.downcast_ref::<ParseError>() {
+                return Some(match error {
+                    ParseError::ReadError(_) => Kind::Io,
+                    _ => Kind::Parse,
+                });
+            }
+            if cause.is::<io::Error>() {
+                return Some(Kind::Io);
+            }
+            None
+        });
+        match kind {
+            Some(Kind::Parse) => Error::Parse(e),
+            Some(Kind::Io) => Error::Io(e),
+            Some(Kind::Schema) => Error::Schema(e),
+            Some(Kind::Sink) | None => Error::Sink(e),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
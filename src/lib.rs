@@ -1,9 +1,9 @@
 #![deny(rust_2018_idioms)]
 
-#[macro_use]
-extern crate lazy_static;
+pub use error::Error;
 
 pub mod database;
+pub mod error;
 pub mod parser;
 pub mod read;
 pub mod yajlish;
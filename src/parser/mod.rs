@@ -1,149 +1,169 @@
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
-use std::mem::swap;
-use std::string::String;
+use std::sync::Arc;
 use std::vec::Vec;
 
 use anyhow::Result;
 use serde_json::Value as JsonValue;
 
-pub use models::{JsonPath, TableLocation, TableRecord};
+pub use models::{
+    JsonPath, Location, ParsedRow, PathId, PathInterner, Pointer, Row, RowSource, RowValues,
+    TableLocation, TableRecord,
+};
 
-use crate::yajlish::{Context, Handler, Status};
+use crate::yajlish::{Context, Handler, Position, Status};
 
 pub mod models;
 
+/// Number of values of an object up to which repeated keys are found by scanning
+const LINEAR_SCAN_LEN: usize = 16;
+
 /// Handles objects within list
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ObjectHandler {
     object_id: i32,
-    path: JsonPath,
-    // Current object id within list
-    rec: TableRecord,
+    // Ids of paths to keys of nested objects being built
+    path: Vec<PathId>,
+    // Values of the current object, the buffer is reused for following objects
+    values: Vec<(PathId, JsonValue)>,
+    // Position of each path in values, filled only for objects with many values
+    positions: HashMap<PathId, usize>,
 }
 
 impl ObjectHandler {
     pub fn new() -> ObjectHandler {
-        ObjectHandler {
-            object_id: 0,
-            path: JsonPath::new(),
-            rec: TableRecord::new(),
-        }
+        ObjectHandler::default()
     }
 
-    /// Optionally produces a record if it's creation is finished
-    fn pop(&mut self) -> Option<(i32, TableRecord)> {
-        // Do not produce elements if we are in the process of building object
-        if self.path.len() != 0 {
-            return None;
-        }
+    /// Object is complete unless we are in the process of building it
+    fn is_complete(&self) -> bool {
+        self.path.is_empty()
+    }
 
-        let mut new_rec = TableRecord::new();
-        swap(&mut self.rec, &mut new_rec);
-        let ret_value = Some((self.object_id, new_rec));
+    /// Start next object once the complete one is consumed
+    fn next_object(&mut self) {
+        self.values.clear();
+        self.positions.clear();
         self.object_id += 1;
-        return ret_value;
+    }
+
+    fn current_path(&self) -> PathId {
+        self.path.last().copied().unwrap_or(PathInterner::EMPTY)
     }
 
     fn handle_json_value(&mut self, val: JsonValue) {
-        self.rec.insert(self.path.clone(), val);
+        let path = self.current_path();
+        // Keys repeated within object keep their first position, like in `TableRecord`
+        let position = if self.values.len() < LINEAR_SCAN_LEN {
+            self.values.iter().position(|(p, _)| *p == path)
+        } else {
+            if self.positions.is_empty() {
+                let positions = self.values.iter().enumerate().map(|(i, (p, _))| (*p, i));
+                self.positions.extend(positions);
+            }
+            self.positions.get(&path).copied()
+        };
+        match position {
+            Some(i) => self.values[i].1 = val,
+            None => {
+                if !self.positions.is_empty() {
+                    self.positions.insert(path, self.values.len());
+                }
+                self.values.push((path, val));
+            }
+        }
     }
 
-    fn handle_start_map(&mut self) {
-        self.path.push(String::from(""));
+    fn handle_start_map(&mut self, paths: &mut PathInterner) {
+        let path = paths.child(self.current_path(), "");
+        self.path.push(path);
     }
 
     fn handle_end_map(&mut self) {
         self.path.pop();
     }
 
-    fn handle_map_key(&mut self, key: &str) {
-        *self.path.last_mut().unwrap() = String::from(key);
+    fn handle_map_key(&mut self, paths: &mut PathInterner, key: &str) {
+        let parent = match self.path.len() {
+            0 | 1 => PathInterner::EMPTY,
+            len => self.path[len - 2],
+        };
+        *self.path.last_mut().unwrap() = paths.child(parent, key);
     }
 }
 
-/// Tree supported by HashMap that allows traversing up and down by JsonPath
+/// Object handler of a single table with links to handlers of parent and child tables
+#[derive(Debug)]
+struct TableNode {
+    handler: ObjectHandler,
+    parent_id: usize,
+    children: HashMap<PathId, usize>,
+    // Interned table path, shared by records of the table
+    table_path: Arc<[JsonPath]>,
+}
+
+/// Tree of table nodes that allows traversing up and down by interned paths
 struct ObjectHandlerHashTree {
     // List of all nodes
-    // Nodes contain link to all child ids and parent id
-    arena: Vec<(ObjectHandler, usize, HashMap<JsonPath, usize>)>,
+    arena: Vec<TableNode>,
     // Defines current node
     current_id: usize,
-
-    // Support field stored for fast access
-    current_path: Vec<JsonPath>,
 }
 
 impl ObjectHandlerHashTree {
     pub fn new() -> ObjectHandlerHashTree {
         ObjectHandlerHashTree {
-            arena: vec![(ObjectHandler::new(), 0, HashMap::new())],
-            current_path: Vec::new(),
+            arena: vec![TableNode {
+                handler: ObjectHandler::new(),
+                parent_id: 0,
+                children: HashMap::new(),
+                table_path: Arc::from(Vec::new()),
+            }],
             current_id: 0,
         }
     }
 
-    pub fn full_path(&mut self) -> &Vec<JsonPath> {
-        &self.current_path
-    }
-
-    fn current_tup(&self) -> &(ObjectHandler, usize, HashMap<JsonPath, usize>) {
-        &self.arena[self.current_id]
-    }
-
-    fn current_tup_mut(&mut self) -> &mut (ObjectHandler, usize, HashMap<JsonPath, usize>) {
-        &mut self.arena[self.current_id]
-    }
-
-    fn parent_tup(&self) -> Option<&(ObjectHandler, usize, HashMap<JsonPath, usize>)> {
-        let parent_id = self.current_tup().1;
-        if parent_id != self.current_id {
-            Some(&self.arena[parent_id])
-        } else {
-            None
-        }
-    }
-
     pub fn current(&self) -> &ObjectHandler {
-        &self.current_tup().0
+        &self.arena[self.current_id].handler
     }
 
     pub fn current_mut(&mut self) -> &mut ObjectHandler {
-        &mut self.current_tup_mut().0
+        &mut self.arena[self.current_id].handler
     }
 
-    pub fn parent(&self) -> Option<&ObjectHandler> {
-        match self.parent_tup() {
-            Some(t) => Some(&t.0),
-            None => None,
+    /// Object id of parent table handler, root table has none
+    pub fn parent_object_id(&self) -> i32 {
+        let parent_id = self.arena[self.current_id].parent_id;
+        if parent_id != self.current_id {
+            self.arena[parent_id].handler.object_id
+        } else {
+            0
         }
     }
 
     pub fn go_up(&mut self) {
-        let cur_obj = self.current_tup();
-        let parent_id = cur_obj.1;
-        self.current_id = parent_id;
-        self.current_path.pop();
+        self.current_id = self.arena[self.current_id].parent_id;
     }
 
-    pub fn go_down(&mut self, path: &JsonPath) {
-        // Update current path
-        self.current_path.push(path.clone());
-
-        let current_itm = self.current_tup();
-
-        match current_itm.2.get(path) {
+    pub fn go_down(&mut self, path: PathId, paths: &PathInterner) {
+        match self.arena[self.current_id].children.get(&path) {
             Some(down_id) => {
-                self.current_id = down_id.clone();
+                self.current_id = *down_id;
             }
             None => {
-                // Add link to child object to current object
-                let new_id = self.arena.len().clone();
-                self.current_tup_mut().2.insert(path.clone(), new_id);
-
-                // Create and insert new object, set current id to new object
-                self.arena
-                    .push((ObjectHandler::new(), self.current_id, HashMap::new()));
+                // Add link to child node to current node
+                let new_id = self.arena.len();
+                self.arena[self.current_id].children.insert(path, new_id);
+
+                let mut table_path = self.arena[self.current_id].table_path.to_vec();
+                table_path.push(paths.path(path).clone());
+                let table_path = Arc::from(table_path);
+                self.arena.push(TableNode {
+                    handler: ObjectHandler::new(),
+                    parent_id: self.current_id,
+                    children: HashMap::new(),
+                    table_path,
+                });
                 self.current_id = new_id;
             }
         };
@@ -154,8 +174,14 @@ pub struct NestedObjectHandler<'a> {
     // Path to current database being processed
     handler_stack: ObjectHandlerHashTree,
 
-    // stack of object handlers
-    consumer: &'a mut dyn FnMut(TableLocation, TableRecord) -> Result<()>,
+    // Json paths of the document
+    paths: PathInterner,
+
+    // Json pointer to the current value
+    pointer: Pointer,
+
+    // Receives rows of complete objects
+    consumer: &'a mut dyn FnMut(&mut ParsedRow<'_>) -> Result<()>,
 
     // Accumulates errors on parsing
     // Aborts on first error
@@ -170,10 +196,12 @@ impl<'a> Debug for NestedObjectHandler<'a> {
 
 impl<'a> NestedObjectHandler<'a> {
     pub fn new(
-        consumer: &'a mut dyn FnMut(TableLocation, TableRecord) -> Result<()>,
+        consumer: &'a mut dyn FnMut(&mut ParsedRow<'_>) -> Result<()>,
     ) -> NestedObjectHandler<'a> {
         NestedObjectHandler {
             handler_stack: ObjectHandlerHashTree::new(),
+            paths: PathInterner::new(),
+            pointer: Pointer::default(),
             error: None,
             consumer,
         }
@@ -187,43 +215,47 @@ impl<'a> NestedObjectHandler<'a> {
         self.handler_stack.current_mut()
     }
 
-    fn parent_handler(&self) -> Option<&ObjectHandler> {
-        self.handler_stack.parent()
+    /// Number elements of the top-level array from `index`, for chunks of a larger array
+    pub fn set_first_index(&mut self, index: usize) {
+        self.pointer.set_first_index(index);
     }
 
-    fn try_pop(&mut self) -> Result<()> {
-        match self.current_handler_mut().pop() {
-            Some((rec_id, rec)) => {
-                let table_location = TableLocation {
-                    table_path: self.handler_stack.full_path().clone(),
-                    object_id: rec_id,
-                    parent_object_id: match self.parent_handler() {
-                        Some(p) => p.object_id,
-                        None => 0,
-                    },
-                };
-                (self.consumer)(table_location, rec)
-            }
-            None => Ok(()),
+    /// Location of the current value at `position` of the parser
+    pub fn location(&self, position: Position) -> Location {
+        Location {
+            position,
+            pointer: self.pointer.format(&self.paths),
         }
     }
-}
 
-impl<'a> Handler for NestedObjectHandler<'a> {
-    fn handle_json_value(&mut self, _ctx: &Context, val: JsonValue) -> Status {
-        self.current_handler_mut().handle_json_value(val);
-        match self.try_pop() {
-            Ok(_) => Status::Continue,
-            Err(e) => {
-                self.error = Some(e);
-                Status::Abort
-            }
+    fn try_pop(&mut self, ctx: &Context) -> Result<()> {
+        if !self.current_handler().is_complete() {
+            return Ok(());
         }
+
+        let parent_object_id = self.handler_stack.parent_object_id();
+        let node = &mut self.handler_stack.arena[self.handler_stack.current_id];
+        let mut row = ParsedRow::new(
+            &node.table_path,
+            node.handler.object_id,
+            parent_object_id,
+            &mut node.handler.values,
+            RowSource {
+                position: ctx.position(),
+                pointer: &self.pointer,
+                paths: &self.paths,
+            },
+        );
+        let result = (self.consumer)(&mut row);
+        self.current_handler_mut().next_object();
+        result.map_err(|e| e.context(self.location(ctx.position())))
     }
 
-    fn handle_start_map(&mut self, _ctx: &Context) -> Status {
-        self.current_handler_mut().handle_start_map();
-        match self.try_pop() {
+    /// Pop complete object, aborting on error
+    fn finish_value(&mut self, ctx: &Context) -> Status {
+        let result = self.try_pop(ctx);
+        self.pointer.next_value();
+        match result {
             Ok(_) => Status::Continue,
             Err(e) => {
                 self.error = Some(e);
@@ -231,37 +263,45 @@ impl<'a> Handler for NestedObjectHandler<'a> {
             }
         }
     }
+}
+
+impl<'a> Handler for NestedObjectHandler<'a> {
+    fn handle_json_value(&mut self, ctx: &Context, val: JsonValue) -> Status {
+        self.current_handler_mut().handle_json_value(val);
+        self.finish_value(ctx)
+    }
+
+    fn handle_start_map(&mut self, _ctx: &Context) -> Status {
+        self.handler_stack
+            .current_mut()
+            .handle_start_map(&mut self.paths);
+        self.pointer.start_map();
+        Status::Continue
+    }
 
-    fn handle_end_map(&mut self, _ctx: &Context) -> Status {
+    fn handle_end_map(&mut self, ctx: &Context) -> Status {
         self.current_handler_mut().handle_end_map();
-        match self.try_pop() {
-            Ok(_) => Status::Continue,
-            Err(e) => {
-                self.error = Some(e);
-                Status::Abort
-            }
-        }
+        self.pointer.end();
+        self.finish_value(ctx)
     }
 
     fn handle_map_key(&mut self, _ctx: &Context, key: &str) -> Status {
-        self.current_handler_mut().handle_map_key(key);
+        let handler = self.handler_stack.current_mut();
+        handler.handle_map_key(&mut self.paths, key);
+        self.pointer.map_key(handler.current_path());
         Status::Continue
     }
 
     fn handle_start_array(&mut self, _ctx: &Context) -> Status {
-        let current_path = &self.current_handler().path.clone();
-        self.handler_stack.go_down(current_path);
+        let current_path = self.current_handler().current_path();
+        self.handler_stack.go_down(current_path, &self.paths);
+        self.pointer.start_array();
         Status::Continue
     }
 
-    fn handle_end_array(&mut self, _ctx: &Context) -> Status {
+    fn handle_end_array(&mut self, ctx: &Context) -> Status {
         self.handler_stack.go_up();
-        match self.try_pop() {
-            Ok(_) => Status::Continue,
-            Err(e) => {
-                self.error = Some(e);
-                Status::Abort
-            }
-        }
+        self.pointer.end();
+        self.finish_value(ctx)
     }
 }
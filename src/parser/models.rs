@@ -1,13 +1,21 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use indexmap::IndexMap;
 use serde_json::Value as JsonValue;
 
+use crate::yajlish::Position;
+
 /// Path in json file without nested tables
 pub type JsonPath = Vec<String>;
 
-pub type TableRecord = HashMap<JsonPath, JsonValue>;
+/// Values of a single object, ordered by first appearance in the document
+pub type TableRecord = IndexMap<JsonPath, JsonValue>;
 
-#[derive(Debug)]
+/// Id of json path interned by `PathInterner`
+pub type PathId = u32;
+
+#[derive(Debug, Clone)]
 pub struct TableLocation {
     pub table_path: Vec<JsonPath>,
     pub object_id: i32,
@@ -21,3 +29,320 @@ impl TableLocation {
         rv
     }
 }
+
+/// Interns json paths of a document, so that values refer to their path by a small id
+///
+/// Paths form a trie, keys are looked up among children of the parent path
+/// without allocating.
+#[derive(Debug)]
+pub struct PathInterner {
+    paths: Vec<JsonPath>,
+    children: Vec<HashMap<String, PathId>>,
+}
+
+impl Default for PathInterner {
+    fn default() -> Self {
+        PathInterner {
+            paths: vec![JsonPath::new()],
+            children: vec![HashMap::new()],
+        }
+    }
+}
+
+impl PathInterner {
+    /// Id of the empty path
+    pub const EMPTY: PathId = 0;
+
+    pub fn new() -> PathInterner {
+        PathInterner::default()
+    }
+
+    /// Id of path `parent` extended by `key`
+    pub fn child(&mut self, parent: PathId, key: &str) -> PathId {
+        if let Some(id) = self.children[parent as usize].get(key) {
+            return *id;
+        }
+        let id = self.paths.len() as PathId;
+        let mut path = self.paths[parent as usize].clone();
+        path.push(String::from(key));
+        self.paths.push(path);
+        self.children.push(HashMap::new());
+        self.children[parent as usize].insert(String::from(key), id);
+        id
+    }
+
+    pub fn path(&self, id: PathId) -> &JsonPath {
+        &self.paths[id as usize]
+    }
+
+    /// Number of interned paths, including the empty one
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Never true, the empty path is always interned
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Paths with ids from `start` on, in the order of their ids
+    pub fn paths_from(&self, start: usize) -> &[JsonPath] {
+        &self.paths[start.min(self.paths.len())..]
+    }
+
+    /// Add paths interned by another interner, taken from it by `paths_from(self.len())`,
+    /// so that ids of both refer to the same paths. Keys are not looked up in added paths
+    pub fn extend(&mut self, paths: Vec<JsonPath>) {
+        self.children
+            .extend(std::iter::repeat_with(HashMap::new).take(paths.len()));
+        self.paths.extend(paths);
+    }
+}
+
+/// Key of object or index in array, leading to a nested value
+#[derive(Debug, Clone, Copy)]
+enum PointerPart {
+    // Interned path ending with the key, the empty path until the key is known
+    Key(PathId),
+    Index(usize),
+}
+
+/// Json pointer to the value being parsed
+#[derive(Debug, Default)]
+pub struct Pointer {
+    parts: Vec<PointerPart>,
+    // Index of the first element of the top-level array
+    first_index: usize,
+}
+
+impl Pointer {
+    pub fn start_map(&mut self) {
+        self.parts.push(PointerPart::Key(PathInterner::EMPTY));
+    }
+
+    pub fn map_key(&mut self, path: PathId) {
+        if let Some(last) = self.parts.last_mut() {
+            *last = PointerPart::Key(path);
+        }
+    }
+
+    pub fn start_array(&mut self) {
+        let first = if self.parts.is_empty() {
+            self.first_index
+        } else {
+            0
+        };
+        self.parts.push(PointerPart::Index(first));
+    }
+
+    /// Number elements of the top-level array from `index`, for chunks of a larger array
+    pub fn set_first_index(&mut self, index: usize) {
+        self.first_index = index;
+    }
+
+    /// Leave the map or array just ended
+    pub fn end(&mut self) {
+        self.parts.pop();
+    }
+
+    /// Move to the next element once a value of array is complete
+    pub fn next_value(&mut self) {
+        if let Some(PointerPart::Index(i)) = self.parts.last_mut() {
+            *i += 1;
+        }
+    }
+
+    /// Pointer as defined by RFC 6901, e.g. `/books/0/title`
+    pub fn format(&self, paths: &PathInterner) -> String {
+        let mut rv = String::new();
+        for part in self.parts.iter() {
+            match part {
+                PointerPart::Key(path) => {
+                    if let Some(key) = paths.path(*path).last() {
+                        rv.push('/');
+                        rv.push_str(&key.replace('~', "~0").replace('/', "~1"));
+                    }
+                }
+                PointerPart::Index(i) => {
+                    rv.push('/');
+                    rv.push_str(&i.to_string());
+                }
+            }
+        }
+        rv
+    }
+}
+
+/// Place in the document where parsing or writing failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub position: Position,
+    /// Json pointer to the value, empty for the whole document
+    pub pointer: String,
+}
+
+impl Location {
+    /// Location within a larger input, of which the parsed one is a part starting at `start`
+    pub fn offset_by(self, start: Position) -> Location {
+        Location {
+            position: self.position.offset_by(start),
+            pointer: self.pointer,
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.position)?;
+        if !self.pointer.is_empty() {
+            write!(f, " at {}", self.pointer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where in the document the parser completed a row
+#[derive(Clone, Copy)]
+pub struct RowSource<'a> {
+    pub position: Position,
+    pub pointer: &'a Pointer,
+    pub paths: &'a PathInterner,
+}
+
+impl<'a> RowSource<'a> {
+    pub fn location(&self) -> Location {
+        Location {
+            position: self.position,
+            pointer: self.pointer.format(self.paths),
+        }
+    }
+}
+
+/// Values of a single object, either borrowed from the parser or from a record
+#[derive(Clone, Copy)]
+pub enum RowValues<'a> {
+    Interned {
+        values: &'a [(PathId, JsonValue)],
+        paths: &'a PathInterner,
+    },
+    Record(&'a TableRecord),
+}
+
+impl<'a> RowValues<'a> {
+    pub fn len(&self) -> usize {
+        match self {
+            RowValues::Interned { values, .. } => values.len(),
+            RowValues::Record(rec) => rec.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Path and value at `index`, in the order of first appearance in the document
+    pub fn get_index(&self, index: usize) -> Option<(&'a JsonPath, &'a JsonValue)> {
+        match *self {
+            RowValues::Interned { values, paths } => values
+                .get(index)
+                .map(|(path_id, value)| (paths.path(*path_id), value)),
+            RowValues::Record(rec) => rec.get_index(index),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'a JsonPath, &'a JsonValue)> + '_ {
+        (0..self.len()).filter_map(|index| self.get_index(index))
+    }
+
+    pub fn to_record(&self) -> TableRecord {
+        self.iter()
+            .map(|(path, value)| (path.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// Row of a complete object handed over by the parser, whose values may be taken
+/// instead of copied
+pub struct ParsedRow<'a> {
+    pub table_path: &'a Arc<[JsonPath]>,
+    pub object_id: i32,
+    pub parent_object_id: i32,
+    values: &'a mut Vec<(PathId, JsonValue)>,
+    pub paths: &'a PathInterner,
+    pub source: RowSource<'a>,
+}
+
+impl<'a> ParsedRow<'a> {
+    pub fn new(
+        table_path: &'a Arc<[JsonPath]>,
+        object_id: i32,
+        parent_object_id: i32,
+        values: &'a mut Vec<(PathId, JsonValue)>,
+        source: RowSource<'a>,
+    ) -> ParsedRow<'a> {
+        ParsedRow {
+            table_path,
+            object_id,
+            parent_object_id,
+            values,
+            paths: source.paths,
+            source,
+        }
+    }
+
+    /// Row borrowing the values, which are empty once taken
+    pub fn row(&self) -> Row<'_> {
+        Row {
+            table_path: self.table_path,
+            object_id: self.object_id,
+            parent_object_id: self.parent_object_id,
+            values: RowValues::Interned {
+                values: self.values,
+                paths: self.paths,
+            },
+            source: Some(self.source),
+        }
+    }
+
+    /// Take values referring to their paths by ids of `paths`
+    pub fn take_values(&mut self) -> Vec<(PathId, JsonValue)> {
+        std::mem::take(self.values)
+    }
+}
+
+/// Record of a single object, borrowed from the parser until it moves on
+#[derive(Clone, Copy)]
+pub struct Row<'a> {
+    pub table_path: &'a [JsonPath],
+    pub object_id: i32,
+    pub parent_object_id: i32,
+    pub values: RowValues<'a>,
+    /// Unknown for rows of records
+    pub source: Option<RowSource<'a>>,
+}
+
+impl<'a> Row<'a> {
+    pub fn from_record(loc: &'a TableLocation, rec: &'a TableRecord) -> Row<'a> {
+        Row {
+            table_path: &loc.table_path,
+            object_id: loc.object_id,
+            parent_object_id: loc.parent_object_id,
+            values: RowValues::Record(rec),
+            source: None,
+        }
+    }
+
+    pub fn parent_table_path(&self) -> &'a [JsonPath] {
+        self.table_path
+            .split_last()
+            .map_or(self.table_path, |(_, parent)| parent)
+    }
+
+    pub fn location(&self) -> TableLocation {
+        TableLocation {
+            table_path: self.table_path.to_vec(),
+            object_id: self.object_id,
+            parent_object_id: self.parent_object_id,
+        }
+    }
+}
@@ -1,17 +1,49 @@
 use std::collections::HashMap;
-use std::io::BufRead;
+use std::io::{self, BufRead, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use anyhow::{Context, Result};
+use memchr::{memchr_iter, memrchr};
+use serde_json::Value as JsonValue;
 
 use crate::database::Database;
-use crate::parser::{JsonPath, NestedObjectHandler, TableLocation, TableRecord};
-use crate::yajlish::Parser;
+use crate::parser::{
+    JsonPath, Location, NestedObjectHandler, ParsedRow, PathId, PathInterner, Row, RowValues,
+    TableLocation, TableRecord,
+};
+use crate::yajlish::{Parser, Position};
+use crate::Error;
 
-pub fn read_to_db<D: Database, B: BufRead>(mut database: D, mut reader: B) -> Result<()> {
-    let mut consumer = |loc: TableLocation, rec: TableRecord| database.write(loc, rec);
+pub fn read_to_db<D: Database, B: BufRead>(database: D, reader: B) -> Result<(), Error> {
+    read_documents(database, reader, false).map_err(Error::from)
+}
+
+/// Like `read_to_db`, but input holds json documents one after another, e.g. json lines.
+/// Every document makes a root record, ids continue across documents
+pub fn read_lines_to_db<D: Database, B: BufRead>(database: D, reader: B) -> Result<(), Error> {
+    read_documents(database, reader, true).map_err(Error::from)
+}
+
+fn read_documents<D: Database, B: BufRead>(
+    mut database: D,
+    mut reader: B,
+    multiple_values: bool,
+) -> Result<()> {
+    let mut consumer = |parsed: &mut ParsedRow<'_>| {
+        database
+            .write_row(&parsed.row())
+            .map_err(anyhow::Error::from)
+    };
     let mut handler = NestedObjectHandler::new(&mut consumer);
-    let mut parser = Parser::new(&mut handler);
-    parser.parse(&mut reader).context("Could not parse json")?;
+    parse(
+        &mut handler,
+        &mut reader,
+        multiple_values,
+        Position::default(),
+    )?;
 
     match handler.error {
         None => Ok(()),
@@ -19,17 +51,42 @@ pub fn read_to_db<D: Database, B: BufRead>(mut database: D, mut reader: B) -> Re
     }
 }
 
+/// Feed input to handler, accepting several documents if `multiple_values`.
+/// Errors are located as within input starting at `start`
+fn parse<B: BufRead>(
+    handler: &mut NestedObjectHandler<'_>,
+    reader: &mut B,
+    multiple_values: bool,
+    start: Position,
+) -> Result<()> {
+    let mut parser = Parser::new(handler);
+    parser.allow_multiple_values(multiple_values);
+    let result = parser.parse(reader);
+    let position = parser.context().position();
+    let result = result.and_then(|()| parser.finish_parse());
+
+    match result {
+        // Parsing stopped early by an error of the handler is reported by the caller
+        Err(_) if handler.error.is_some() => Ok(()),
+        Err(e) => Err(anyhow::Error::from(e)
+            .context(handler.location(position).offset_by(start))
+            .context("Could not parse json")),
+        Ok(()) => Ok(()),
+    }
+}
+
 /// Remaps ids from local parsers to global
 /// local parsers can be potentially executed in parallel and they do yield ids in local space
 ///
-/// Keeps track of latest ids in each table
-/// Keeps track to which ids was initial data remapped
+/// Parsers number objects of each table from zero in the order they first appear,
+/// so ids of a document are shifted by the number of ids given out before it.
+/// Documents are remapped one after another.
 struct IdRemapper {
-    // Keeps track latest ids for tables
+    // Number of ids given out in each table
     tables: HashMap<Vec<JsonPath>, i32>,
 
-    // Keeps track of remapped ids from specific parsers
-    remap_store: HashMap<usize, HashMap<Vec<JsonPath>, HashMap<i32, i32>>>,
+    // Offset and number of local ids of each table, per document being remapped
+    remap_store: HashMap<usize, HashMap<Vec<JsonPath>, (i32, i32)>>,
 }
 
 impl IdRemapper {
@@ -47,50 +104,46 @@ impl IdRemapper {
     }
 
     pub fn finish_remapper(&mut self, remapper_id: usize) {
-        self.remap_store.remove(&remapper_id);
+        if let Some(offsets) = self.remap_store.remove(&remapper_id) {
+            for (table_path, (offset, local_ids)) in offsets {
+                let ids = self.tables.entry(table_path).or_insert(0);
+                *ids = (*ids).max(offset + local_ids);
+            }
+        }
+    }
+
+    /// Global id of local object id
+    fn find_obj_id(&mut self, remapper_id: usize, table_path: &[JsonPath], object_id: i32) -> i32 {
+        let offsets = self.remap_store.get_mut(&remapper_id).unwrap();
+        if let Some((offset, local_ids)) = offsets.get_mut(table_path) {
+            *local_ids = (*local_ids).max(object_id + 1);
+            return *offset + object_id;
+        }
+        // Table appears first in the document
+        let offset = self.tables.get(table_path).copied().unwrap_or(0);
+        offsets.insert(table_path.to_vec(), (offset, object_id + 1));
+        offset + object_id
     }
 
-    /// Find object id in mappers for tables or if does not exist create it and return
-    fn find_obj_id(
+    /// Map object id of remapper to an already known global id
+    fn share_obj_id(
         &mut self,
         remapper_id: usize,
-        table_path: &Vec<JsonPath>,
+        table_path: &[JsonPath],
         object_id: i32,
-    ) -> i32 {
-        let remapper = self.remap_store.get_mut(&remapper_id).unwrap();
-        let remapper_id_store = match remapper.get_mut(table_path) {
-            Some(m) => m,
-            None => {
-                remapper.insert(table_path.clone(), HashMap::new());
-                remapper.get_mut(table_path).unwrap()
-            }
-        };
-        match remapper_id_store.get_mut(&object_id) {
-            Some(i) => *i, // Object id found in id map
-            None => {
-                // No object id found in id map
-                let current_id = match self.tables.get_mut(table_path) {
-                    Some(i) => i,
-                    None => {
-                        self.tables.insert(table_path.clone(), 0);
-                        self.tables.get_mut(table_path).unwrap()
-                    }
-                };
-                // Clone current id before incrementing, increment and return cloned
-                let insertion_id = current_id.clone();
-                // Remember id to which we mapped the data
-                remapper_id_store.insert(object_id, insertion_id);
-                *current_id += 1;
-                insertion_id
-            }
-        }
+        global_id: i32,
+    ) {
+        self.remap_store
+            .get_mut(&remapper_id)
+            .unwrap()
+            .insert(table_path.to_vec(), (global_id - object_id, object_id + 1));
     }
 
-    pub fn remap_ids(&mut self, remapper_id: usize, mut loc: TableLocation) -> TableLocation {
-        loc.object_id = self.find_obj_id(remapper_id, &loc.table_path, loc.object_id);
-        loc.parent_object_id =
-            self.find_obj_id(remapper_id, &loc.parent_table_path(), loc.parent_object_id);
-        loc
+    pub fn remap_row<'r>(&mut self, remapper_id: usize, mut row: Row<'r>) -> Row<'r> {
+        row.object_id = self.find_obj_id(remapper_id, row.table_path, row.object_id);
+        row.parent_object_id =
+            self.find_obj_id(remapper_id, row.parent_table_path(), row.parent_object_id);
+        row
     }
 }
 
@@ -98,6 +151,15 @@ pub fn read_to_db_many<D: Database, B: BufRead, C>(
     database: &mut D,
     readers: Vec<(C, B)>,
     callback_success: &mut dyn FnMut(C, usize),
+) -> Result<(), Error> {
+    read_many(database, readers, false, callback_success).map_err(Error::from)
+}
+
+fn read_many<D: Database, B: BufRead, C>(
+    database: &mut D,
+    readers: Vec<(C, B)>,
+    json_lines: bool,
+    callback_success: &mut dyn FnMut(C, usize),
 ) -> Result<()> {
     let mut id_remapper = IdRemapper::new();
 
@@ -105,15 +167,14 @@ pub fn read_to_db_many<D: Database, B: BufRead, C>(
         let remapper_id = id_remapper.start_remapper();
         let mut num_records: usize = 0;
 
-        let mut consumer = |mut loc: TableLocation, rec: TableRecord| {
-            loc = id_remapper.remap_ids(remapper_id, loc);
+        let mut consumer = |parsed: &mut ParsedRow<'_>| {
+            let row = id_remapper.remap_row(remapper_id, parsed.row());
             num_records += 1;
-            database.write(loc, rec)
+            database.write_row(&row).map_err(anyhow::Error::from)
         };
 
         let mut handler = NestedObjectHandler::new(&mut consumer);
-        let mut parser = Parser::new(&mut handler);
-        parser.parse(&mut reader).context("Could not parse json")?;
+        parse(&mut handler, &mut reader, json_lines, Position::default())?;
 
         match handler.error {
             None => {}
@@ -129,3 +190,884 @@ pub fn read_to_db_many<D: Database, B: BufRead, C>(
 
     Ok(())
 }
+
+/// Number of records sent from parsing thread to writer at once
+const BATCH_SIZE: usize = 1024;
+
+/// Number of batches a parsing thread may get ahead of the writer for a single part
+const BATCHES_AHEAD: usize = 64;
+
+/// Approximate size of parts inputs are split into unless configured otherwise
+pub const DEFAULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Settings of reading inputs on worker threads
+#[derive(Clone, Copy)]
+pub struct ReadOptions {
+    /// Number of threads parsing inputs
+    pub jobs: usize,
+    /// Approximate number of bytes in chunks that top-level arrays and json lines
+    /// are split into, so that a single large input is parsed by several threads
+    pub chunk_size: usize,
+    /// Inputs hold json documents one after another, e.g. one per line,
+    /// each one making a root record
+    pub json_lines: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            jobs: 1,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            json_lines: false,
+        }
+    }
+}
+
+/// Row of a parsed object owning its values, which refer to paths by ids of the parser
+struct OwnedRow {
+    table_path: Arc<[JsonPath]>,
+    object_id: i32,
+    parent_object_id: i32,
+    values: Vec<(PathId, JsonValue)>,
+    // Location of the object it was made of
+    location: Location,
+}
+
+/// Rows of a document sent from parsing thread to writer at once, with paths interned
+/// by the parser since the previous batch of the document
+#[derive(Default)]
+struct RowBatch {
+    paths: Vec<JsonPath>,
+    rows: Vec<OwnedRow>,
+}
+
+impl OwnedRow {
+    /// Row borrowing values, whose paths are resolved by `paths`
+    fn row<'r>(&'r self, paths: &'r PathInterner) -> Row<'r> {
+        Row {
+            table_path: &self.table_path,
+            object_id: self.object_id,
+            parent_object_id: self.parent_object_id,
+            values: RowValues::Interned {
+                values: &self.values,
+                paths,
+            },
+            source: None,
+        }
+    }
+}
+
+impl RowBatch {
+    fn with_capacity(capacity: usize) -> RowBatch {
+        RowBatch {
+            paths: Vec::new(),
+            rows: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Take values of row located within input starting at `start`, along with
+    /// paths interned after the first `known_paths`
+    fn push(&mut self, parsed: &mut ParsedRow<'_>, start: Position, known_paths: &mut usize) {
+        self.paths
+            .extend_from_slice(parsed.paths.paths_from(*known_paths));
+        *known_paths = parsed.paths.len();
+        self.rows.push(OwnedRow {
+            table_path: parsed.table_path.clone(),
+            object_id: parsed.object_id,
+            parent_object_id: parsed.parent_object_id,
+            values: parsed.take_values(),
+            location: parsed.source.location().offset_by(start),
+        });
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+}
+
+/// Record skipped in tolerant mode, as it could not be parsed
+#[derive(Debug)]
+pub struct Reject {
+    /// Start of the record in its input
+    pub position: Position,
+    pub error: String,
+    /// Raw bytes of the record
+    pub bytes: Vec<u8>,
+}
+
+/// Receives rejects of tolerant mode along with the input they come from
+pub type RejectsCallback<'a, C> = dyn FnMut(&C, Reject) -> Result<()> + 'a;
+
+/// Output of parsing a part of input, ids are local to the document
+enum Parsed {
+    Rows(RowBatch),
+    EndOfDocument,
+    /// Malformed record of tolerant mode, ending the document instead of its records
+    Rejected(Reject),
+}
+
+/// Input parsed by a single worker
+enum Input<B> {
+    /// Whole document
+    Reader(B),
+    /// Elements of a top-level array, enclosed in brackets, and index of the first one
+    ArrayChunk(Vec<u8>, usize),
+    /// Json documents one after another, cut between documents
+    Documents(Vec<u8>),
+}
+
+struct Work<B> {
+    input: Input<B>,
+    // Position of the input within the file, array chunks start one byte early
+    // for the opening bracket
+    start: Position,
+    sender: SyncSender<Result<Parsed>>,
+}
+
+/// Parts of inputs in the order they are written
+enum Part {
+    /// Documents, each with its own ids
+    Documents(Receiver<Result<Parsed>>),
+    /// Chunk of top-level array, sharing the root object with other chunks of the array
+    ArrayChunk {
+        receiver: Receiver<Result<Parsed>>,
+        last: bool,
+    },
+    EndOfFile,
+}
+
+/// Parse single document, or several ones if `multiple_values`, sending records in batches.
+/// Returns false if parsing failed or the writer stopped
+fn parse_document<B: BufRead>(
+    mut reader: B,
+    multiple_values: bool,
+    start: Position,
+    first_index: usize,
+    sender: &SyncSender<Result<Parsed>>,
+) -> bool {
+    let mut batch = RowBatch::with_capacity(BATCH_SIZE);
+    let mut known_paths = PathInterner::new().len();
+    let mut consumer = |parsed: &mut ParsedRow<'_>| {
+        batch.push(parsed, start, &mut known_paths);
+        if batch.len() >= BATCH_SIZE {
+            let full = std::mem::replace(&mut batch, RowBatch::with_capacity(BATCH_SIZE));
+            sender
+                .send(Ok(Parsed::Rows(full)))
+                .map_err(|_| anyhow::anyhow!("Writer stopped"))?;
+        }
+        Ok(())
+    };
+    let mut handler = NestedObjectHandler::new(&mut consumer);
+    handler.set_first_index(first_index);
+    let result = parse(&mut handler, &mut reader, multiple_values, start);
+    let result = match (result, handler.error.take()) {
+        (Err(e), _) => Err(e),
+        (Ok(()), Some(e)) => Err(e.context("Parsing finished with an error")),
+        (Ok(()), None) => Ok(()),
+    };
+
+    match result {
+        Ok(()) => {
+            sender.send(Ok(Parsed::Rows(batch))).is_ok()
+                && sender.send(Ok(Parsed::EndOfDocument)).is_ok()
+        }
+        Err(e) => {
+            let _ = sender.send(Err(e));
+            false
+        }
+    }
+}
+
+/// Parse `text` starting at `start` of a single record in tolerant mode, sending its records
+/// only once all of them are parsed, or the raw `record` at `position` as reject.
+/// Returns false if the writer stopped
+fn parse_record(
+    text: &[u8],
+    start: Position,
+    first_index: usize,
+    record: &[u8],
+    position: Position,
+    sender: &SyncSender<Result<Parsed>>,
+) -> bool {
+    let mut rows = RowBatch::default();
+    let mut known_paths = PathInterner::new().len();
+    let mut consumer = |parsed: &mut ParsedRow<'_>| {
+        rows.push(parsed, start, &mut known_paths);
+        Ok(())
+    };
+    let mut handler = NestedObjectHandler::new(&mut consumer);
+    handler.set_first_index(first_index);
+    let result = parse(&mut handler, &mut &text[..], false, start);
+
+    match result {
+        Ok(()) => {
+            sender.send(Ok(Parsed::Rows(rows))).is_ok()
+                && sender.send(Ok(Parsed::EndOfDocument)).is_ok()
+        }
+        Err(e) => reject(position, format!("{:#}", e), record, sender),
+    }
+}
+
+fn reject(
+    position: Position,
+    error: String,
+    record: &[u8],
+    sender: &SyncSender<Result<Parsed>>,
+) -> bool {
+    let reject = Reject {
+        position,
+        error,
+        bytes: record.to_vec(),
+    };
+    sender.send(Ok(Parsed::Rejected(reject))).is_ok()
+}
+
+/// Positions of bytes in part of input starting at `start`, looked up in increasing order
+struct Positions<'a> {
+    bytes: &'a [u8],
+    index: usize,
+    position: Position,
+}
+
+impl<'a> Positions<'a> {
+    fn new(bytes: &'a [u8], start: Position) -> Positions<'a> {
+        Positions {
+            bytes,
+            index: 0,
+            position: start,
+        }
+    }
+
+    fn at(&mut self, index: usize) -> Position {
+        let skipped = &self.bytes[self.index..index];
+        match memrchr(b'\n', skipped) {
+            Some(i) => {
+                self.position.line += memchr_iter(b'\n', skipped).count();
+                self.position.column = skipped.len() - i;
+            }
+            None => self.position.column += skipped.len(),
+        }
+        self.position.offset += skipped.len();
+        self.index = index;
+        self.position
+    }
+}
+
+/// Part of `bytes` starting at `start` without surrounding whitespace, and its position
+fn trimmed(bytes: &[u8], start: usize, end: usize) -> (usize, &[u8]) {
+    let part = &bytes[start..end];
+    let skip = part.iter().take_while(|b| b.is_ascii_whitespace()).count();
+    let len = part[skip..]
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    (start + skip, &part[skip..skip + len])
+}
+
+/// Elements of array chunk with their positions in the chunk, and position of the closing
+/// bracket unless the array is not closed
+fn array_elements(chunk: &[u8]) -> (Vec<(usize, &[u8])>, Option<usize>) {
+    let mut elements = Vec::new();
+    let mut start = 1;
+    let mut depth: usize = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, b) in chunk.iter().enumerate().skip(1) {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if *b == b'\\' {
+                escaped = true;
+            } else if *b == b'"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' if depth == 0 => {
+                let element = trimmed(chunk, start, i);
+                // Nothing but whitespace is an empty array rather than a missing element
+                if !elements.is_empty() || !element.1.is_empty() {
+                    elements.push(element);
+                }
+                return (elements, Some(i));
+            }
+            b']' | b'}' => depth -= 1,
+            b',' if depth == 0 => {
+                elements.push(trimmed(chunk, start, i));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    elements.push(trimmed(chunk, start, chunk.len()));
+    (elements, None)
+}
+
+fn parse_input<B: BufRead>(
+    input: Input<B>,
+    start: Position,
+    tolerant: bool,
+    sender: &SyncSender<Result<Parsed>>,
+) {
+    match input {
+        Input::Reader(reader) => {
+            parse_document(reader, false, start, 0, sender);
+        }
+        Input::ArrayChunk(bytes, first_index) if tolerant => {
+            // Elements are parsed one by one, enclosed in brackets to keep their table
+            let (elements, closed) = array_elements(&bytes);
+            let mut positions = Positions::new(&bytes, start);
+            let mut text = Vec::new();
+            for (i, (element_start, element)) in elements.iter().enumerate() {
+                let position = positions.at(*element_start);
+                let sent = if element.is_empty() {
+                    let error = String::from("Missing array element");
+                    reject(position, error, element, sender)
+                } else if closed.is_none() && i + 1 == elements.len() {
+                    // Element cut short at the end of input may still parse
+                    let error = String::from("Array is not closed");
+                    reject(position, error, element, sender)
+                } else {
+                    text.clear();
+                    text.push(b'[');
+                    text.extend_from_slice(element);
+                    text.push(b']');
+                    // Opening bracket stands right before the element
+                    let text_start = Position {
+                        offset: position.offset - 1,
+                        line: position.line,
+                        column: position.column - 1,
+                    };
+                    let index = first_index + i;
+                    parse_record(&text, text_start, index, element, position, sender)
+                };
+                if !sent {
+                    return;
+                }
+            }
+            if let Some(end) = closed {
+                let (rest_start, rest) = trimmed(&bytes, end + 1, bytes.len());
+                if !rest.is_empty() {
+                    let error = String::from("Unexpected data after the end of array");
+                    reject(positions.at(rest_start), error, rest, sender);
+                }
+            }
+        }
+        Input::ArrayChunk(bytes, first_index) => {
+            parse_document(bytes.as_slice(), false, start, first_index, sender);
+        }
+        Input::Documents(bytes) if tolerant => {
+            let mut positions = Positions::new(&bytes, start);
+            let mut line_end = 0;
+            for raw_line in bytes.split(|b| *b == b'\n') {
+                let (line_start, line) = trimmed(&bytes, line_end, line_end + raw_line.len());
+                line_end += raw_line.len() + 1;
+                if line.is_empty() {
+                    continue;
+                }
+                let position = positions.at(line_start);
+                if !parse_record(line, position, 0, line, position, sender) {
+                    return;
+                }
+            }
+        }
+        Input::Documents(bytes) => {
+            parse_document(bytes.as_slice(), true, start, 0, sender);
+        }
+    }
+}
+
+/// Reader counting consumed bytes and lines, giving positions of parts of input
+struct Counted<B> {
+    reader: B,
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl<B: BufRead> Counted<B> {
+    fn new(reader: B) -> Counted<B> {
+        Counted {
+            reader,
+            offset: 0,
+            line: 1,
+            line_start: 0,
+        }
+    }
+
+    fn position(&self) -> Position {
+        Position {
+            offset: self.offset,
+            line: self.line,
+            column: self.offset - self.line_start + 1,
+        }
+    }
+
+    fn count(&mut self, lines: usize, last_line_break: Option<usize>, len: usize) {
+        if let Some(i) = last_line_break {
+            self.line += lines;
+            self.line_start = self.offset + i + 1;
+        }
+        self.offset += len;
+    }
+}
+
+impl<B: BufRead> Read for Counted<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        let read = &buf[..len];
+        self.count(memchr_iter(b'\n', read).count(), memrchr(b'\n', read), len);
+        Ok(len)
+    }
+}
+
+impl<B: BufRead> BufRead for Counted<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Consumed bytes were returned by the last `fill_buf`, which returns them again
+        let (lines, last_line_break) = match self.reader.fill_buf() {
+            Ok(buf) => {
+                let consumed = &buf[..amt.min(buf.len())];
+                (
+                    memchr_iter(b'\n', consumed).count(),
+                    memrchr(b'\n', consumed),
+                )
+            }
+            Err(_) => (0, None),
+        };
+        self.count(lines, last_line_break, amt);
+        self.reader.consume(amt);
+    }
+}
+
+/// Splits inputs into parts, handing them to workers and announcing them to the writer
+struct Splitter<'a, B> {
+    options: ReadOptions,
+    tolerant: bool,
+    work: SyncSender<Work<B>>,
+    parts: SyncSender<Part>,
+    stop: &'a AtomicBool,
+}
+
+impl<'a, B: BufRead> Splitter<'a, B> {
+    fn split_all(&self, readers: Vec<B>) {
+        for reader in readers {
+            if self.stop.load(Ordering::SeqCst) {
+                return;
+            }
+            if let Err(e) = self.split(reader) {
+                // Writer reports the error as if parsing failed
+                let (sender, receiver) = sync_channel(1);
+                let _ = sender.send(Err(e));
+                let _ = self.parts.send(Part::Documents(receiver));
+                return;
+            }
+            if self.parts.send(Part::EndOfFile).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn split(&self, reader: B) -> Result<()> {
+        let mut reader = Counted::new(reader);
+        if self.options.json_lines {
+            self.split_documents(reader)
+        } else if (self.options.jobs > 1 || self.tolerant) && starts_with_array(&mut reader)? {
+            self.split_array(reader)
+        } else {
+            // Leading whitespace may have been skipped
+            let start = reader.position();
+            self.submit(Input::Reader(reader.reader), start, None)
+        }
+    }
+
+    /// Send input to workers, `last` tells whether array chunk is the last one of array
+    fn submit(&self, input: Input<B>, start: Position, last: Option<bool>) -> Result<()> {
+        let (sender, receiver) = sync_channel(BATCHES_AHEAD);
+        let part = match last {
+            Some(last) => Part::ArrayChunk { receiver, last },
+            None => Part::Documents(receiver),
+        };
+        self.work
+            .send(Work {
+                input,
+                start,
+                sender,
+            })
+            .map_err(|_| anyhow::anyhow!("Workers stopped"))?;
+        self.parts
+            .send(part)
+            .map_err(|_| anyhow::anyhow!("Writer stopped"))
+    }
+
+    /// Cut chunks at line breaks between documents
+    ///
+    /// Documents may span several lines, so line breaks within them are skipped.
+    /// In tolerant mode every line is a document of its own.
+    fn split_documents(&self, mut reader: Counted<B>) -> Result<()> {
+        let mut chunk = Vec::new();
+        let mut start = reader.position();
+        let mut depth: usize = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        loop {
+            let line_start = chunk.len();
+            let len = reader
+                .read_until(b'\n', &mut chunk)
+                .context("Could not read input")?;
+            for b in chunk[line_start..].iter() {
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if *b == b'\\' {
+                        escaped = true;
+                    } else if *b == b'"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+                match b {
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    // Unbalanced input is left for the parser to report
+                    b']' | b'}' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+
+            let between_documents = self.tolerant || (depth == 0 && !in_string);
+            if len == 0 || (between_documents && chunk.len() >= self.options.chunk_size) {
+                if !chunk.is_empty() {
+                    self.submit(Input::Documents(std::mem::take(&mut chunk)), start, None)?;
+                    start = reader.position();
+                }
+                if len == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Cut chunks of whole elements at commas between elements of the array
+    ///
+    /// The last chunk keeps the closing bracket and anything after it,
+    /// so that parser sees malformed endings as they are.
+    fn split_array(&self, mut reader: Counted<B>) -> Result<()> {
+        let mut start = reader.position();
+        reader.consume(1);
+        let mut chunk = vec![b'['];
+        let mut depth: usize = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut closed = false;
+        // Index of the first element of the chunk, and of the element being read
+        let mut first_index = 0;
+        let mut index = 0;
+
+        loop {
+            let buf = reader.fill_buf().context("Could not read input")?;
+            if buf.is_empty() {
+                return self.submit(Input::ArrayChunk(chunk, first_index), start, Some(true));
+            }
+
+            let mut cut = None;
+            for (i, b) in buf.iter().enumerate() {
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if *b == b'\\' {
+                        escaped = true;
+                    } else if *b == b'"' {
+                        in_string = false;
+                    }
+                    continue;
+                }
+                match b {
+                    b'"' => in_string = true,
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' if depth == 0 => closed = true,
+                    b']' | b'}' => depth -= 1,
+                    // Chunk of whitespace only would read as an empty array
+                    b',' if depth == 0
+                        && !closed
+                        && chunk.len() + i >= self.options.chunk_size
+                        && !chunk[1..]
+                            .iter()
+                            .chain(&buf[..i])
+                            .all(u8::is_ascii_whitespace)
+                        && starts_element(&buf[i + 1..]) =>
+                    {
+                        cut = Some(i);
+                        break;
+                    }
+                    b',' if depth == 0 && !closed => index += 1,
+                    _ => {}
+                }
+            }
+
+            match cut {
+                Some(i) => {
+                    chunk.extend_from_slice(&buf[..i]);
+                    chunk.push(b']');
+                    reader.consume(i + 1);
+                    self.submit(Input::ArrayChunk(chunk, first_index), start, Some(false))?;
+                    chunk = vec![b'['];
+                    index += 1;
+                    first_index = index;
+                    // Opening bracket of the next chunk stands for the comma
+                    let after_comma = reader.position();
+                    start = Position {
+                        offset: after_comma.offset - 1,
+                        line: after_comma.line,
+                        column: after_comma.column - 1,
+                    };
+                }
+                None => {
+                    let len = buf.len();
+                    chunk.extend_from_slice(buf);
+                    reader.consume(len);
+                }
+            }
+        }
+    }
+}
+
+/// Tell whether an element follows the comma before `rest`
+///
+/// Cutting before a closing bracket would turn a trailing comma into an empty
+/// chunk, so such comma is left for the parser to reject. Same goes for comma
+/// whose next element is not within `rest`.
+fn starts_element(rest: &[u8]) -> bool {
+    rest.iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|b| !matches!(b, b']' | b'}'))
+}
+
+/// Skip leading whitespace and tell whether document is an array
+fn starts_with_array<B: BufRead>(reader: &mut B) -> Result<bool> {
+    loop {
+        let buf = reader.fill_buf().context("Could not read input")?;
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let is_array = buf[i] == b'[';
+                reader.consume(i);
+                return Ok(is_array);
+            }
+            None if buf.is_empty() => return Ok(false),
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Like `read_to_db_many`, but parses inputs on `jobs` worker threads
+///
+/// Top-level arrays, and inputs of json lines, are split into chunks parsed
+/// independently. Records are written from the calling thread in input order with
+/// ids remapped as in `read_to_db_many`, so output does not depend on `jobs`.
+pub fn read_to_db_many_parallel<D: Database, B: BufRead + Send, C>(
+    database: &mut D,
+    readers: Vec<(C, B)>,
+    options: &ReadOptions,
+    callback_success: &mut dyn FnMut(C, usize),
+) -> Result<(), Error> {
+    let result = if options.jobs <= 1 {
+        read_many(database, readers, options.json_lines, callback_success)
+    } else {
+        read_parallel(database, readers, options, None, callback_success)
+    };
+    result.map_err(Error::from)
+}
+
+/// Like `read_to_db_many_parallel`, but skips malformed records of json lines and
+/// top-level arrays, handing them to `rejects` along with their input
+///
+/// Every line of json lines, and every element of a top-level array, is parsed on its
+/// own, so that nothing of a rejected record is written and ids of the others stay
+/// consecutive. Json lines can't span several lines then. Other inputs still fail on
+/// the first error, as does an error returned by `rejects`.
+pub fn read_to_db_many_tolerant<D: Database, B: BufRead + Send, C>(
+    database: &mut D,
+    readers: Vec<(C, B)>,
+    options: &ReadOptions,
+    rejects: &mut RejectsCallback<'_, C>,
+    callback_success: &mut dyn FnMut(C, usize),
+) -> Result<(), Error> {
+    read_parallel(database, readers, options, Some(rejects), callback_success).map_err(Error::from)
+}
+
+fn read_parallel<D: Database, B: BufRead + Send, C>(
+    database: &mut D,
+    readers: Vec<(C, B)>,
+    options: &ReadOptions,
+    rejects: Option<&mut RejectsCallback<'_, C>>,
+    callback_success: &mut dyn FnMut(C, usize),
+) -> Result<()> {
+    let (args, readers): (Vec<C>, Vec<B>) = readers.into_iter().unzip();
+    let jobs = options.jobs.max(1);
+    let tolerant = rejects.is_some();
+    let (work_sender, work_receiver) = sync_channel::<Work<B>>(jobs);
+    let work_receiver = Mutex::new(work_receiver);
+    let (part_sender, part_receiver) = sync_channel(jobs * 2);
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let splitter = Splitter {
+            options: *options,
+            tolerant,
+            work: work_sender,
+            parts: part_sender,
+            stop: &stop,
+        };
+        scope.spawn(move || splitter.split_all(readers));
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let work = work_receiver.lock().unwrap().recv();
+                match work {
+                    Ok(work) if !stop.load(Ordering::SeqCst) => {
+                        parse_input(work.input, work.start, tolerant, &work.sender)
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            });
+        }
+
+        let result = write_parts(database, args, part_receiver, rejects, callback_success);
+        // Receivers dropped with the parts make blocked workers give up
+        stop.store(true, Ordering::SeqCst);
+        result
+    })
+}
+
+/// Write records of parts in their order, remapping ids of each document
+fn write_parts<D: Database, C>(
+    database: &mut D,
+    args: Vec<C>,
+    parts: Receiver<Part>,
+    mut rejects: Option<&mut RejectsCallback<'_, C>>,
+    callback_success: &mut dyn FnMut(C, usize),
+) -> Result<()> {
+    let mut id_remapper = IdRemapper::new();
+    let mut args = args.into_iter().peekable();
+    let mut num_records: usize = 0;
+    // Global id of root object of the array being written in chunks
+    let mut array_root: Option<i32> = None;
+    let root_path = Vec::<JsonPath>::new();
+    let tolerant = rejects.is_some();
+
+    let mut report = |args: Option<&C>, reject: Reject| match (rejects.as_mut(), args) {
+        (Some(rejects), Some(args)) => rejects(args, reject),
+        _ => Ok(()),
+    };
+
+    for part in parts {
+        match part {
+            Part::Documents(receiver) => {
+                let mut remapper_id = id_remapper.start_remapper();
+                // Paths interned by the parser of the current document
+                let mut paths = PathInterner::new();
+                for parsed in receiver {
+                    match parsed? {
+                        Parsed::Rows(batch) => {
+                            paths.extend(batch.paths);
+                            for row in batch.rows {
+                                let row_ref = id_remapper.remap_row(remapper_id, row.row(&paths));
+                                num_records += 1;
+                                database
+                                    .write_row(&row_ref)
+                                    .map_err(|e| e.context(row.location))?;
+                            }
+                        }
+                        Parsed::EndOfDocument => {
+                            id_remapper.finish_remapper(remapper_id);
+                            remapper_id = id_remapper.start_remapper();
+                            paths = PathInterner::new();
+                        }
+                        Parsed::Rejected(reject) => {
+                            report(args.peek(), reject)?;
+                            id_remapper.finish_remapper(remapper_id);
+                            remapper_id = id_remapper.start_remapper();
+                            paths = PathInterner::new();
+                        }
+                    }
+                }
+                id_remapper.finish_remapper(remapper_id);
+            }
+            Part::ArrayChunk { receiver, last } => {
+                let mut remapper_id = id_remapper.start_remapper();
+                let root_id = match array_root {
+                    Some(root_id) => {
+                        id_remapper.share_obj_id(remapper_id, &root_path, 0, root_id);
+                        root_id
+                    }
+                    None => {
+                        let root_id = id_remapper.find_obj_id(remapper_id, &root_path, 0);
+                        array_root = Some(root_id);
+                        root_id
+                    }
+                };
+                let mut paths = PathInterner::new();
+                for parsed in receiver {
+                    match parsed? {
+                        Parsed::Rows(batch) => {
+                            paths.extend(batch.paths);
+                            for row in batch.rows {
+                                // Every chunk closes the array, only the last one makes the
+                                // root record. Elements parsed one by one make it below
+                                if (!last || tolerant) && row.table_path.is_empty() {
+                                    continue;
+                                }
+                                let row_ref = id_remapper.remap_row(remapper_id, row.row(&paths));
+                                num_records += 1;
+                                database
+                                    .write_row(&row_ref)
+                                    .map_err(|e| e.context(row.location))?;
+                            }
+                        }
+                        Parsed::EndOfDocument => {
+                            id_remapper.finish_remapper(remapper_id);
+                            remapper_id = id_remapper.start_remapper();
+                            id_remapper.share_obj_id(remapper_id, &root_path, 0, root_id);
+                            paths = PathInterner::new();
+                        }
+                        Parsed::Rejected(reject) => {
+                            report(args.peek(), reject)?;
+                            id_remapper.finish_remapper(remapper_id);
+                            remapper_id = id_remapper.start_remapper();
+                            id_remapper.share_obj_id(remapper_id, &root_path, 0, root_id);
+                            paths = PathInterner::new();
+                        }
+                    }
+                }
+                id_remapper.finish_remapper(remapper_id);
+
+                if last && tolerant {
+                    let loc = TableLocation {
+                        table_path: root_path.clone(),
+                        object_id: root_id,
+                        parent_object_id: 0,
+                    };
+                    num_records += 1;
+                    database.write(loc, TableRecord::new())?;
+                }
+            }
+            Part::EndOfFile => {
+                array_root = None;
+                if let Some(args) = args.next() {
+                    (callback_success)(args, num_records);
+                }
+                num_records = 0;
+            }
+        }
+    }
+    Ok(())
+}
@@ -68,6 +68,53 @@ pub enum ParserStatus {
     GotValue,
 }
 
+/// Position of a token in the input.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+    /// Number of bytes before the token.
+    pub offset: usize,
+    /// Line of the token, starting from 1.
+    pub line: usize,
+    /// Column of the token in bytes, starting from 1.
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    /// Position within a larger input, of which the parsed one is a part starting at `start`.
+    #[must_use]
+    pub fn offset_by(self, start: Position) -> Position {
+        Position {
+            offset: start.offset + self.offset,
+            line: start.line + self.line - 1,
+            column: if self.line == 1 {
+                start.column + self.column - 1
+            } else {
+                self.column
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {} (byte {})",
+            self.line, self.column, self.offset
+        )
+    }
+}
+
 /// The context passed to each Handler function. Context gives
 /// basic information about where it is at in the json document.
 #[derive(Debug)]
@@ -77,6 +124,7 @@ pub struct Context {
     status: ParserStatus,
     num_open_braces: usize,
     num_open_brackets: usize,
+    position: Position,
 }
 
 impl Default for Context {
@@ -87,6 +135,7 @@ impl Default for Context {
 
             num_open_braces: 0,
             num_open_brackets: 0,
+            position: Position::default(),
         }
     }
 }
@@ -112,6 +161,16 @@ impl Context {
         self.status
     }
 
+    /// Position of the latest token, or of the end of input once it is reached.
+    #[must_use]
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    pub(crate) fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+
     /// Update the parser status.
     pub(crate) fn update_status(&mut self, status: ParserStatus) {
         self.status = status;
@@ -0,0 +1,342 @@
+use std::io::BufRead;
+
+use memchr::{memchr2, memchr_iter, memrchr};
+use serde_json::Number as JsonNumber;
+
+use super::{ParseError, Position};
+
+/// Kind of token read by `Lexer`, text of strings and numbers is kept by the lexer
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Token {
+    BracketOpen,
+    BracketClose,
+    CurlyOpen,
+    CurlyClose,
+    Comma,
+    Colon,
+    Null,
+    True,
+    False,
+    Number,
+    String,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Token::BracketOpen => "'['",
+            Token::BracketClose => "']'",
+            Token::CurlyOpen => "'{'",
+            Token::CurlyClose => "'}'",
+            Token::Comma => "','",
+            Token::Colon => "':'",
+            Token::Null => "null",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Number => "number",
+            Token::String => "string",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Tokenizer reading whole buffers of the underlying reader
+///
+/// Strings are scanned with `memchr` for quotes and escapes. Tokens lying within
+/// a single buffer are decoded in place, others are collected in a scratch buffer.
+pub struct Lexer<'a, B: ?Sized> {
+    reader: &'a mut B,
+    // Raw bytes of the current string or number
+    scratch: Vec<u8>,
+    // Decoded text of the current string or number
+    text: String,
+    // Number of bytes consumed, line and offset of its start
+    offset: usize,
+    line: usize,
+    line_start: usize,
+    // Start of the current token
+    token: Position,
+}
+
+impl<'a, B: BufRead + ?Sized> Lexer<'a, B> {
+    pub fn new(reader: &'a mut B) -> Self {
+        Lexer {
+            reader,
+            scratch: Vec::new(),
+            text: String::new(),
+            offset: 0,
+            line: 1,
+            line_start: 0,
+            token: Position::default(),
+        }
+    }
+
+    /// Start of the last token, or end of input once it is reached
+    pub fn position(&self) -> Position {
+        self.token
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.reader.consume(len);
+        self.offset += len;
+    }
+
+    /// Read next token, `None` at the end of input
+    pub fn next_token(&mut self) -> Result<Option<Token>, ParseError> {
+        let first = loop {
+            let buf = self.reader.fill_buf()?;
+            let start = buf.iter().position(|b| !is_whitespace(*b));
+            // Line breaks only occur in whitespace, as strings can't hold them
+            let whitespace = &buf[..start.unwrap_or(buf.len())];
+            if let Some(i) = memrchr(b'\n', whitespace) {
+                self.line += memchr_iter(b'\n', whitespace).count();
+                self.line_start = self.offset + i + 1;
+            }
+            let len = whitespace.len();
+            let first = start.map(|i| buf[i]);
+            self.token = Position {
+                offset: self.offset + len,
+                line: self.line,
+                column: self.offset + len - self.line_start + 1,
+            };
+            match first {
+                Some(first) => {
+                    self.consume(len + 1);
+                    break first;
+                }
+                None if len == 0 => return Ok(None),
+                None => self.consume(len),
+            }
+        };
+
+        let token = match first {
+            b'[' => Token::BracketOpen,
+            b']' => Token::BracketClose,
+            b'{' => Token::CurlyOpen,
+            b'}' => Token::CurlyClose,
+            b',' => Token::Comma,
+            b':' => Token::Colon,
+            b'"' => {
+                self.read_string()?;
+                Token::String
+            }
+            b'-' | b'0'..=b'9' => {
+                self.read_number(first)?;
+                Token::Number
+            }
+            b't' => {
+                self.expect_literal(b"rue")?;
+                Token::True
+            }
+            b'f' => {
+                self.expect_literal(b"alse")?;
+                Token::False
+            }
+            b'n' => {
+                self.expect_literal(b"ull")?;
+                Token::Null
+            }
+            b => {
+                return Err(ParseError::MalformedJson(format!(
+                    "Unexpected character {:?}",
+                    char::from(b)
+                )))
+            }
+        };
+        Ok(Some(token))
+    }
+
+    /// Text of the last string or number token
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Copy of text of the last string or number token
+    pub fn take_text(&mut self) -> String {
+        self.text.as_str().to_owned()
+    }
+
+    /// Number of the last number token
+    pub fn number(&self) -> Result<JsonNumber, ParseError> {
+        let text = self.text.as_str();
+        let digits = text.strip_prefix('-').unwrap_or(text);
+        // Plain integers skip the general number parser
+        if digits.len() < 19
+            && digits.bytes().all(|b| b.is_ascii_digit())
+            && (digits.len() == 1 || !digits.starts_with('0'))
+            && !(text.starts_with('-') && digits == "0")
+        {
+            if let Ok(i) = text.parse::<i64>() {
+                return Ok(JsonNumber::from(i));
+            }
+        }
+        serde_json::from_str::<JsonNumber>(text)
+            .map_err(|_| ParseError::MalformedJson(format!("Invalid number {}", text)))
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>, ParseError> {
+        let buf = self.reader.fill_buf()?;
+        match buf.first().copied() {
+            Some(b) => {
+                self.consume(1);
+                Ok(Some(b))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn expect_literal(&mut self, rest: &[u8]) -> Result<(), ParseError> {
+        for expected in rest {
+            if self.next_byte()? != Some(*expected) {
+                return Err(ParseError::MalformedJson(String::from("Invalid literal")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set text to `bytes`, checking they are utf-8
+    fn set_text(text: &mut String, bytes: &[u8]) -> Result<(), ParseError> {
+        let s = std::str::from_utf8(bytes).map_err(|e| ParseError::Utf8Error(e.to_string()))?;
+        text.clear();
+        text.push_str(s);
+        Ok(())
+    }
+
+    fn finish_text(&mut self) -> Result<(), ParseError> {
+        Self::set_text(&mut self.text, &self.scratch)
+    }
+
+    fn read_number(&mut self, first: u8) -> Result<(), ParseError> {
+        let buf = self.reader.fill_buf()?;
+        let len = buf.iter().position(|b| !is_number_byte(*b));
+        if let Some(len) = len {
+            // Number ends within the buffer
+            self.text.clear();
+            self.text.push(char::from(first));
+            self.text.extend(buf[..len].iter().map(|b| char::from(*b)));
+            self.consume(len);
+            return Ok(());
+        }
+
+        self.scratch.clear();
+        self.scratch.push(first);
+        loop {
+            let buf = self.reader.fill_buf()?;
+            let len = buf
+                .iter()
+                .position(|b| !is_number_byte(*b))
+                .unwrap_or(buf.len());
+            self.scratch.extend_from_slice(&buf[..len]);
+            let at_end = len < buf.len() || buf.is_empty();
+            self.consume(len);
+            if at_end {
+                return self.finish_text();
+            }
+        }
+    }
+
+    /// Read string after the opening quote, decoding escapes
+    fn read_string(&mut self) -> Result<(), ParseError> {
+        let buf = self.reader.fill_buf()?;
+        if let Some(i) = memchr2(b'"', b'\\', buf) {
+            if buf[i] == b'"' && !buf[..i].iter().any(|b| *b < 0x20) {
+                // String without escapes ends within the buffer
+                Self::set_text(&mut self.text, &buf[..i])?;
+                self.consume(i + 1);
+                return Ok(());
+            }
+        }
+
+        self.scratch.clear();
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Err(ParseError::MalformedJson(String::from(
+                    "Unterminated string",
+                )));
+            }
+            let end = memchr2(b'"', b'\\', buf);
+            let segment = &buf[..end.unwrap_or(buf.len())];
+            if segment.iter().any(|b| *b < 0x20) {
+                return Err(ParseError::MalformedJson(String::from(
+                    "Control character in string",
+                )));
+            }
+            self.scratch.extend_from_slice(segment);
+            match end {
+                Some(i) => {
+                    let special = buf[i];
+                    self.consume(i + 1);
+                    if special == b'"' {
+                        return self.finish_text();
+                    }
+                    self.read_escape()?;
+                }
+                None => {
+                    let len = buf.len();
+                    self.consume(len);
+                }
+            }
+        }
+    }
+
+    /// Decode escape sequence after the backslash
+    fn read_escape(&mut self) -> Result<(), ParseError> {
+        let decoded = match self.next_byte()? {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                let high = self.read_hex()?;
+                let code = if (0xD800..0xDC00).contains(&high) {
+                    // Characters outside the basic plane are written as surrogate pairs
+                    if self.next_byte()? != Some(b'\\') || self.next_byte()? != Some(b'u') {
+                        return Err(invalid_escape());
+                    }
+                    let low = self.read_hex()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(invalid_escape());
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                char::from_u32(code).ok_or_else(invalid_escape)?
+            }
+            _ => return Err(invalid_escape()),
+        };
+        let mut utf8 = [0; 4];
+        self.scratch
+            .extend_from_slice(decoded.encode_utf8(&mut utf8).as_bytes());
+        Ok(())
+    }
+
+    fn read_hex(&mut self) -> Result<u32, ParseError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next_byte()?
+                .and_then(|b| char::from(b).to_digit(16))
+                .ok_or_else(invalid_escape)?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+}
+
+fn is_number_byte(b: u8) -> bool {
+    matches!(b, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\n' | b'\r' | b'\t')
+}
+
+fn invalid_escape() -> ParseError {
+    ParseError::MalformedJson(String::from("Invalid escape sequence in string"))
+}
@@ -17,19 +17,20 @@
 
 use std::io::BufRead;
 
-use json_tools::{Buffer, BufferType, Lexer, Token, TokenType};
 use serde_json;
-use serde_json::Number as JsonNumber;
 use serde_json::Value as JsonValue;
 
-pub use common::{Context, Enclosing, Handler, ParserStatus, Status};
+pub use common::{Context, Enclosing, Handler, ParserStatus, Position, Status};
+use lexer::{Lexer, Token};
 
 pub mod common;
+pub mod lexer;
 
 /// Main Parser struct.
 pub struct Parser<'a, H> {
     handler: &'a mut H,
     context: Context,
+    multiple_values: bool,
 }
 
 impl<'a, H: Handler> Parser<'a, H> {
@@ -38,28 +39,55 @@ impl<'a, H: Handler> Parser<'a, H> {
         Parser {
             handler,
             context: Context::default(),
+            multiple_values: false,
         }
     }
 
+    /// Accept several whitespace-separated values, e.g. json lines, one after another.
+    /// Otherwise anything but whitespace after the first value is an error.
+    pub fn allow_multiple_values(&mut self, allow: bool) {
+        self.multiple_values = allow;
+    }
+
     /// Parse until Handler method returns Abort or EOF.
     ///
     /// # Errors
     ///    - Will return an error Result if the JSON is malformed, or if the underlying
-    ///   Reader returns an error.
+    ///      Reader returns an error.
     pub fn parse<B: BufRead>(&mut self, read: &mut B) -> Result<(), ParseError> {
         let context = &mut self.context;
 
-        let mut lexer = Lexer::new(Wrapper(read), BufferType::Bytes(20));
+        let mut lexer = Lexer::new(read);
+        let mut last_token = Token::Null;
 
         while !matches!(
             context.parser_status(),
             ParserStatus::ParseComplete | ParserStatus::LexicalError
         ) {
-            let status = match lexer.next() {
-                Some(Token {
-                    kind: TokenType::BracketClose,
-                    ..
-                }) => {
+            let token = lexer.next_token();
+            context.set_position(lexer.position());
+            let token = token?;
+            if let Some(token) = token {
+                last_token = token;
+            }
+            if token.is_some() && context.parser_status() == ParserStatus::GotValue {
+                if !self.multiple_values {
+                    return Err(ParseError::MalformedJson(String::from(
+                        "Unexpected data after the end of document",
+                    )));
+                }
+                context.update_status(ParserStatus::Start);
+            }
+
+            let status = match token {
+                Some(Token::BracketClose) => {
+                    if !matches!(
+                        context.parser_status(),
+                        ParserStatus::ArrayStart | ParserStatus::ArrayGotVal
+                    ) {
+                        context.update_status(ParserStatus::LexicalError);
+                        continue;
+                    }
                     let status = self.handler.handle_end_array(context);
                     if context.last_enclosing() == Some(Enclosing::LeftBracket) {
                         context.remove_last_enclosing();
@@ -77,10 +105,14 @@ impl<'a, H: Handler> Parser<'a, H> {
                     }
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::CurlyClose,
-                    ..
-                }) => {
+                Some(Token::CurlyClose) => {
+                    if !matches!(
+                        context.parser_status(),
+                        ParserStatus::MapStart | ParserStatus::MapGotVal
+                    ) {
+                        context.update_status(ParserStatus::LexicalError);
+                        continue;
+                    }
                     let status = self.handler.handle_end_map(context);
 
                     if context.last_enclosing() == Some(Enclosing::LeftBrace) {
@@ -100,20 +132,22 @@ impl<'a, H: Handler> Parser<'a, H> {
 
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::BracketOpen,
-                    ..
-                }) => {
+                Some(Token::BracketOpen) => {
+                    if !expects_value(context) {
+                        context.update_status(ParserStatus::LexicalError);
+                        continue;
+                    }
                     let status = self.handler.handle_start_array(context);
                     context.add_enclosing(Enclosing::LeftBracket);
                     context.inc_brackets();
                     context.update_status(ParserStatus::ArrayStart);
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::CurlyOpen,
-                    ..
-                }) => {
+                Some(Token::CurlyOpen) => {
+                    if !expects_value(context) {
+                        context.update_status(ParserStatus::LexicalError);
+                        continue;
+                    }
                     let status = self.handler.handle_start_map(context);
                     context.add_enclosing(Enclosing::LeftBrace);
                     context.inc_braces();
@@ -121,10 +155,7 @@ impl<'a, H: Handler> Parser<'a, H> {
 
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::Null,
-                    ..
-                }) => {
+                Some(Token::Null) => {
                     let status = self
                         .handler
                         .handle_json_value(context, serde_json::Value::Null);
@@ -133,68 +164,41 @@ impl<'a, H: Handler> Parser<'a, H> {
 
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::Number,
-                    buf,
-                }) => {
-                    let status = match buf {
-                        Buffer::MultiByte(b) => match std::str::from_utf8(&b) {
-                            Ok(s) => match serde_json::from_str::<JsonNumber>(s) {
-                                Ok(num) => self
-                                    .handler
-                                    .handle_json_value(context, JsonValue::from(num)),
-                                Err(_) => {
-                                    panic!("Could not parse json number")
-                                }
-                            },
-                            Err(e) => return Err(ParseError::MalformedJson(e.to_string())),
-                        },
-                        Buffer::Span(_) => panic!("Unexpected Span when handling number"),
-                    };
+                Some(Token::Number) => {
+                    let num = lexer.number()?;
+                    let status = self
+                        .handler
+                        .handle_json_value(context, JsonValue::from(num));
 
                     update_context_status_value(context);
 
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::String,
-                    buf,
-                }) => {
-                    // Read raw bytes to string
-                    let string = match buf {
-                        Buffer::MultiByte(ref b) => match std::str::from_utf8(b) {
-                            Ok(s) => s,
-                            Err(e) => return Err(ParseError::MalformedJson(e.to_string())),
-                        },
-                        Buffer::Span(_) => panic!("Unexpected span in string buffer"),
-                    };
-
-                    let string = serde_json::from_str::<String>(string).unwrap();
-
+                Some(Token::String) => {
                     if context.parser_status() == ParserStatus::ArrayNeedVal
                         || context.parser_status() == ParserStatus::ArrayStart
                     {
                         let status = self
                             .handler
-                            .handle_json_value(context, JsonValue::from(string));
+                            .handle_json_value(context, JsonValue::from(lexer.take_text()));
                         context.update_status(ParserStatus::ArrayGotVal);
                         Some(status)
                     } else if context.parser_status() == ParserStatus::MapNeedVal {
                         let status = self
                             .handler
-                            .handle_json_value(context, JsonValue::from(string));
+                            .handle_json_value(context, JsonValue::from(lexer.take_text()));
                         context.update_status(ParserStatus::MapGotVal);
                         Some(status)
                     } else if context.parser_status() == ParserStatus::Start {
                         let status = self
                             .handler
-                            .handle_json_value(context, JsonValue::from(string));
+                            .handle_json_value(context, JsonValue::from(lexer.take_text()));
                         context.update_status(ParserStatus::GotValue);
                         Some(status)
                     } else if context.parser_status() == ParserStatus::MapNeedKey
                         || context.parser_status() == ParserStatus::MapStart
                     {
-                        let status = self.handler.handle_map_key(context, string.as_str());
+                        let status = self.handler.handle_map_key(context, lexer.text());
                         context.update_status(ParserStatus::MapSep);
                         Some(status)
                     } else {
@@ -202,10 +206,7 @@ impl<'a, H: Handler> Parser<'a, H> {
                         None
                     }
                 }
-                Some(Token {
-                    kind: TokenType::BooleanTrue,
-                    ..
-                }) => {
+                Some(Token::True) => {
                     let status = self
                         .handler
                         .handle_json_value(context, JsonValue::from(true));
@@ -214,10 +215,7 @@ impl<'a, H: Handler> Parser<'a, H> {
 
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::BooleanFalse,
-                    ..
-                }) => {
+                Some(Token::False) => {
                     let status = self
                         .handler
                         .handle_json_value(context, JsonValue::from(false));
@@ -226,10 +224,7 @@ impl<'a, H: Handler> Parser<'a, H> {
 
                     Some(status)
                 }
-                Some(Token {
-                    kind: TokenType::Comma,
-                    ..
-                }) => {
+                Some(Token::Comma) => {
                     if context.parser_status() == ParserStatus::MapGotVal {
                         context.update_status(ParserStatus::MapNeedKey);
                     } else if context.parser_status() == ParserStatus::ArrayGotVal {
@@ -240,22 +235,15 @@ impl<'a, H: Handler> Parser<'a, H> {
 
                     None
                 }
-                Some(Token {
-                    kind: TokenType::Colon,
-                    ..
-                }) => {
+                Some(Token::Colon) => {
                     if context.parser_status() == ParserStatus::MapSep {
                         context.update_status(ParserStatus::MapNeedVal);
+                    } else {
+                        context.update_status(ParserStatus::LexicalError);
                     }
 
                     None
                 }
-                Some(Token {
-                    kind: TokenType::Invalid,
-                    buf,
-                }) => {
-                    return Err(ParseError::MalformedJson(format!("{:?}", buf)));
-                }
                 None => {
                     self.context.update_status(ParserStatus::ParseComplete);
                     break;
@@ -268,15 +256,19 @@ impl<'a, H: Handler> Parser<'a, H> {
         }
         if self.context.parser_status() == ParserStatus::LexicalError {
             return Err(ParseError::MalformedJson(format!(
-                "Parse failed due to malformed json: open braces: {}, open brackets: {}",
-                self.context.num_open_braces(),
-                self.context.num_open_brackets()
+                "Unexpected {}",
+                last_token
             )));
         }
 
         Ok(())
     }
 
+    /// The context of the parse, e.g. position of the latest token.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
     /// Parse has already returned from an EOF. This method checks that
     /// there were the right number of closing braces and brackets.
     ///
@@ -288,16 +280,9 @@ impl<'a, H: Handler> Parser<'a, H> {
                 "Did not reach a ParseComplete status".to_owned(),
             ));
         }
-        if self.context.num_open_braces() != 0 {
-            return Err(ParseError::MalformedJson(format!(
-                "Number of open braces: {}, number of open brackets: {}",
-                self.context.num_open_braces(),
-                self.context.num_open_brackets()
-            )));
-        }
-        if self.context.num_open_brackets() != 0 {
+        if self.context.num_open_braces() != 0 || self.context.num_open_brackets() != 0 {
             return Err(ParseError::MalformedJson(format!(
-                "Number of open braces: {}, number of open brackets: {}",
+                "Unexpected end of input, open braces: {}, open brackets: {}",
                 self.context.num_open_braces(),
                 self.context.num_open_brackets()
             )));
@@ -357,6 +342,17 @@ impl From<std::io::Error> for ParseError {
     }
 }
 
+/// Whether a value may start at the current position
+fn expects_value(context: &Context) -> bool {
+    matches!(
+        context.parser_status(),
+        ParserStatus::Start
+            | ParserStatus::ArrayStart
+            | ParserStatus::ArrayNeedVal
+            | ParserStatus::MapNeedVal
+    )
+}
+
 fn update_context_status_value(context: &mut Context) {
     if context.parser_status() == ParserStatus::ArrayNeedVal
         || context.parser_status() == ParserStatus::ArrayStart
@@ -370,20 +366,3 @@ fn update_context_status_value(context: &mut Context) {
         context.update_status(ParserStatus::LexicalError);
     }
 }
-
-struct Wrapper<'a>(&'a mut dyn BufRead);
-
-impl<'a> Iterator for Wrapper<'a> {
-    type Item = u8;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let buffer = self.0.fill_buf().ok();
-        match buffer.map(|b| b.first()).flatten().copied() {
-            Some(b) => {
-                self.0.consume(1);
-                Some(b)
-            }
-            None => None,
-        }
-    }
-}
@@ -1,5 +1,6 @@
 extern crate json_to_tables;
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::path::PathBuf;
@@ -9,8 +10,14 @@ use rstest::*;
 use serde::Serialize;
 use serde_json::{Map, Value as JsonValue};
 
-use json_to_tables::database::DatabaseJson;
+use json_to_tables::database::naming::{naming_strategy, NamingStrategy, SeparatorNaming};
+use json_to_tables::database::{
+    ddl, formats, open_database, ColumnSchema, Database, DatabaseCsv, DatabaseJson, DatabaseJsonl,
+    DatabaseSchema, DatabaseTee, Dialect, FormatOptions, SchemaConfig, ValueType,
+};
+use json_to_tables::parser::{JsonPath, TableLocation, TableRecord};
 use json_to_tables::read;
+use json_to_tables::Error;
 
 /// Convert input stream to tables in json format
 pub fn read_to_json<B: BufRead>(root_name: String, input: B) -> Result<JsonValue> {
@@ -19,28 +26,28 @@ pub fn read_to_json<B: BufRead>(root_name: String, input: B) -> Result<JsonValue
     Ok(result)
 }
 
-fn test_case_path(test_case: &String, expected: bool) -> PathBuf {
+fn test_case_path(test_case: &str, expected: bool) -> PathBuf {
     let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     path.push("tests");
     path.push("resources");
 
     if expected {
-        path.push(test_case.clone() + "-in-expected.json");
+        path.push(test_case.to_string() + "-in-expected.json");
     } else {
-        path.push(test_case.clone() + "-in.json");
+        path.push(test_case.to_string() + "-in.json");
     }
 
     path
 }
 
-fn read_test_case(test_case: &String, expected: bool) -> BufReader<File> {
+fn read_test_case(test_case: &str, expected: bool) -> BufReader<File> {
     let path = test_case_path(test_case, expected);
     let file = File::open(path.as_path())
-        .expect(format!("Could not read test case {}", test_case).as_str());
+        .unwrap_or_else(|_| panic!("Could not read test case {}", test_case));
     BufReader::new(file)
 }
 
-fn write_actual(test_case: &String, obj: &JsonValue) {
+fn write_actual(test_case: &str, obj: &JsonValue) {
     let path = test_case_path(test_case, true);
     let mut file = File::create(path.as_path()).expect("Could not open file");
 
@@ -55,8 +62,10 @@ fn write_actual(test_case: &String, obj: &JsonValue) {
         .expect("Could now write");
 }
 
-fn compare_expected(_expected: &String, _actual_json: &JsonValue) {
-    todo!()
+fn compare_expected(expected: &str, actual_json: &JsonValue) {
+    let expected_json: JsonValue =
+        serde_json::from_str(expected).expect("Could not parse expected json");
+    assert_eq!(&expected_json, actual_json);
 }
 
 #[rstest]
@@ -82,9 +91,1802 @@ fn test_json_to_tables(#[case] test_case: String) {
     let actual = read_to_json(test_case.clone(), &mut read_test_case(&test_case, false))
         .expect("Could not read to json");
 
-    if env!("REWRITE_EXPECTED") == "1" {
+    if std::env::var("REWRITE_EXPECTED").as_deref() == Ok("1") {
         write_actual(&test_case, &actual);
     } else {
         compare_expected(&expected, &actual)
     }
 }
+
+#[rstest]
+#[case("bookstore")]
+#[case("mixed-types")]
+#[case("xbus")]
+fn test_tokens_across_buffers(#[case] test_case: String) {
+    // Every token spans reads of the underlying buffer
+    let expected = read_to_json(test_case.clone(), read_test_case(&test_case, false)).unwrap();
+    let file = File::open(test_case_path(&test_case, false)).unwrap();
+    let actual = read_to_json(test_case, BufReader::with_capacity(1, file)).unwrap();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn test_string_escapes() {
+    let input = r#"{"a\"b": "tab\tquote\"slash\/\\ \u00e9\ud83d\ude00 ß", "n": -0, "f": 1.5e3}"#;
+    for capacity in [1, 3, 8192] {
+        let actual = read_to_json(
+            String::from("root"),
+            BufReader::with_capacity(capacity, input.as_bytes()),
+        )
+        .unwrap();
+        assert_eq!(
+            actual["root"][0]["a\"b"], "tab\tquote\"slash/\\ é😀 ß",
+            "buffer capacity {}",
+            capacity
+        );
+        assert_eq!(
+            actual["root"][0]["n"],
+            serde_json::from_str::<JsonValue>("-0").unwrap()
+        );
+        assert_eq!(
+            actual["root"][0]["f"],
+            serde_json::from_str::<JsonValue>("1.5e3").unwrap()
+        );
+    }
+
+    for invalid in [
+        r#"{"a": "\x"}"#,
+        r#"{"a": "\ud83d"}"#,
+        "{\"a\": \"line\nbreak\"}",
+        r#"{"a": 1.2.3}"#,
+        r#"{"a": tru}"#,
+        r#"{"a": "open"#,
+    ] {
+        assert!(
+            read_to_json(String::from("root"), invalid.as_bytes()).is_err(),
+            "{} should not parse",
+            invalid
+        );
+    }
+}
+
+/// Create empty output directory unique to the test case
+fn output_dir(test_name: &str, test_case: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!(
+        "json-to-tables-{}-{}-{}",
+        test_name,
+        test_case,
+        std::process::id()
+    ));
+    if path.exists() {
+        std::fs::remove_dir_all(&path).expect("Could not clean output directory");
+    }
+    path
+}
+
+/// Split csv content into rows of fields, honoring quoted fields
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => in_quotes = !in_quotes,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    rows
+}
+
+#[rstest]
+#[case("bookstore")]
+#[case("empty_object_in_list")]
+#[case("integration")]
+#[case("mixed-types")]
+#[case("pyramids")]
+#[case("stations")]
+#[case("xbus")]
+fn test_csv_rectangular(#[case] test_case: String) {
+    let path = output_dir("csv", &test_case);
+    let mut db = DatabaseCsv::new(DatabaseSchema::empty(), path.clone()).unwrap();
+    read::read_to_db(&mut db, read_test_case(&test_case, false)).unwrap();
+    db.close().unwrap();
+
+    let mut data_path = path.clone();
+    data_path.push("data");
+    for entry in std::fs::read_dir(&data_path).unwrap() {
+        let entry = entry.unwrap().path();
+        assert_eq!(entry.extension().unwrap(), "csv");
+
+        let content = std::fs::read_to_string(&entry).unwrap();
+        assert!(content.ends_with("\r\n"));
+
+        let rows = parse_csv(&content);
+        let header = &rows[0];
+        assert!(header.iter().all(|name| !name.is_empty()));
+        assert!(rows.len() > 1);
+        for row in rows.iter() {
+            assert_eq!(row.len(), header.len());
+        }
+    }
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[rstest]
+#[case(3)]
+#[case(40)]
+fn test_duplicate_keys_keep_position(#[case] keys: usize) {
+    // Repeated key replaces the value in the column of its first appearance
+    let fields = (0..keys)
+        .map(|i| format!(r#""k{}": {}"#, i, i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let input = format!(r#"{{{}, "k1": "again", "nested": {{"k1": true}}}}"#, fields);
+
+    let path = output_dir("duplicate-keys", &keys.to_string());
+    let mut db = DatabaseCsv::new(DatabaseSchema::empty(), path.clone()).unwrap();
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+
+    let mut data_path = path.clone();
+    data_path.push("data");
+    let entry = std::fs::read_dir(&data_path).unwrap().next().unwrap();
+    let rows = parse_csv(&std::fs::read_to_string(entry.unwrap().path()).unwrap());
+    let k1 = rows[0].iter().position(|name| name == "k1").unwrap();
+    assert_eq!(rows[0].len(), keys + 2);
+    assert_eq!(rows[0][k1 - 1], "k0");
+    assert_eq!(rows[1][k1], "again");
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+/// Read all files in directory into mapping of file name to content
+fn read_dir_contents(path: &PathBuf) -> Vec<(String, Vec<u8>)> {
+    let mut contents = Vec::new();
+    for entry in std::fs::read_dir(path).unwrap() {
+        let entry = entry.unwrap().path();
+        if entry.is_dir() {
+            contents.extend(read_dir_contents(&entry));
+        } else {
+            let name = entry.to_string_lossy().to_string();
+            contents.push((name, std::fs::read(&entry).unwrap()));
+        }
+    }
+    contents.sort();
+    contents
+}
+
+#[rstest]
+#[case("bookstore")]
+#[case("xbus")]
+fn test_csv_deterministic(#[case] test_case: String) {
+    let mut outputs = Vec::new();
+    for run in 0..3 {
+        let path = output_dir(&format!("deterministic-{}", run), &test_case);
+        let mut db = DatabaseCsv::new(DatabaseSchema::empty(), path.clone()).unwrap();
+        read::read_to_db(&mut db, read_test_case(&test_case, false)).unwrap();
+        db.close().unwrap();
+
+        let contents = read_dir_contents(&path)
+            .into_iter()
+            .map(|(name, content)| (name.replace(path.to_str().unwrap(), ""), content))
+            .collect::<Vec<_>>();
+        outputs.push(contents);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+}
+
+#[test]
+fn test_schema_round_trip() {
+    let path = output_dir("schema", "first");
+    let mut db = DatabaseCsv::new(DatabaseSchema::empty(), path.clone()).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let mut schema_path = path.clone();
+    schema_path.push("schema.json");
+    let schema_json = std::fs::read_to_string(&schema_path).unwrap();
+    let schema = DatabaseSchema::load_file(&schema_path).unwrap();
+
+    // Loaded schema serializes back to the same json
+    assert_eq!(
+        serde_json::to_value(&schema).unwrap(),
+        serde_json::from_str::<JsonValue>(&schema_json).unwrap()
+    );
+
+    // Continue from loaded schema, tables and columns keep their positions
+    let second_path = output_dir("schema", "second");
+    let mut db = DatabaseCsv::new(schema, second_path.clone()).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let mut second_schema_path = second_path.clone();
+    second_schema_path.push("schema.json");
+    let second_schema = DatabaseSchema::load_file(&second_schema_path).unwrap();
+    let first_schema = DatabaseSchema::load_file(&schema_path).unwrap();
+    for (first, second) in first_schema.tables().zip(second_schema.tables()) {
+        assert_eq!(first.name, second.name);
+        assert_eq!(first.path, second.path);
+        assert_eq!(first.columns.len(), second.columns.len());
+    }
+    assert_eq!(
+        first_schema.tables().count(),
+        second_schema.tables().count()
+    );
+
+    std::fs::remove_dir_all(&path).unwrap();
+    std::fs::remove_dir_all(&second_path).unwrap();
+}
+
+#[test]
+fn test_schema_without_is_number() {
+    let column = |is_i64: bool, is_f64: bool| {
+        serde_json::json!({"SourceColumn": {
+            "source_path": ["a"], "name": "a", "is_nullable": false, "is_null": false,
+            "is_bool": false, "is_i64": is_i64, "is_f64": is_f64, "example_values": []
+        }})
+    };
+    let schema_json = serde_json::json!({
+        "root_name": "root",
+        "tables": [{
+            "columns": [column(false, true), column(true, false), column(false, false)],
+            "name": "root",
+            "path": []
+        }]
+    });
+    let schema = DatabaseSchema::load(schema_json.to_string().as_bytes()).unwrap();
+    let types = schema
+        .tables()
+        .flat_map(|table| table.columns.iter())
+        .filter_map(|col| match col {
+            ColumnSchema::SourceColumn(col) => Some(col.value_type()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        vec![ValueType::Float, ValueType::Integer, ValueType::String]
+    );
+}
+
+#[test]
+fn test_csv_table_names() {
+    let mut schema = DatabaseSchema::empty();
+    schema
+        .configure(
+            serde_json::from_str::<SchemaConfig>(
+                r#"{
+                    "root_name": "bookstore",
+                    "table_names": [{"path": [["books"], ["authors"]], "name": "authors"}]
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let path = output_dir("table-names", "bookstore");
+    let mut db = DatabaseCsv::new(schema, path.clone()).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let mut data_path = path.clone();
+    data_path.push("data");
+    let mut names = std::fs::read_dir(&data_path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "authors.csv",
+            "books_lin_bookstore.csv",
+            "bookstore.csv",
+            "genres_lin_books_lin_bookstore.csv"
+        ]
+    );
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_table_name_override_clash() {
+    let path = output_dir("table-names", "clash");
+    let mut db = DatabaseCsv::new(DatabaseSchema::empty(), path.clone()).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let mut schema_path = path.clone();
+    schema_path.push("schema.json");
+    let config = |name: &str| {
+        serde_json::from_value::<SchemaConfig>(serde_json::json!({
+            "table_names": [{"path": [["books"], ["authors"]], "name": name}]
+        }))
+        .unwrap()
+    };
+
+    // Name of another existing table is rejected
+    let mut schema = DatabaseSchema::load_file(&schema_path).unwrap();
+    let error = schema.configure(config("root")).unwrap_err();
+    assert!(matches!(error, Error::Schema(_)));
+
+    // Table may keep its own name
+    let mut schema = DatabaseSchema::load_file(&schema_path).unwrap();
+    let own_name = schema
+        .tables()
+        .find(|table| table.name.starts_with("authors"))
+        .unwrap()
+        .name
+        .clone();
+    schema.configure(config(&own_name)).unwrap();
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+fn tricky_paths() -> Vec<JsonPath> {
+    let keys = [
+        "",
+        "a",
+        "in",
+        "inin",
+        "_in_",
+        "a_in_b",
+        "a_in_in_b",
+        "in_a",
+        "a_in",
+        "_",
+        "__",
+        "___",
+        "_a",
+        "tech_a",
+        "techtech_",
+        "empty",
+        "emptyempty",
+        "list",
+        "listlist",
+        "id",
+        "id_",
+        "idid_a",
+        "lin",
+        "a_lin_b",
+        "a.b",
+        ".",
+        "..",
+        "$",
+        "\\",
+        "a\\.b",
+        "a-b",
+        "-",
+        "id.a",
+        "id__a",
+        "привет",
+    ];
+    let mut paths: Vec<JsonPath> = vec![vec![]];
+    for k in keys.iter() {
+        paths.push(vec![k.to_string()]);
+        for l in keys.iter() {
+            paths.push(vec![k.to_string(), l.to_string()]);
+        }
+    }
+    paths
+}
+
+#[rstest]
+#[case("escaped")]
+#[case("dotted")]
+#[case("underscored")]
+fn test_naming_reversible(#[case] naming: String) {
+    let naming = naming_strategy(&naming).unwrap();
+    let root = String::from("root");
+    let paths = tricky_paths();
+
+    let mut column_names = HashSet::new();
+    let mut key_names = HashSet::new();
+    for path in paths.iter() {
+        let name = naming.column_name(path);
+        assert_eq!(
+            naming.parse_column_name(&name).as_ref(),
+            Some(path),
+            "{}",
+            name
+        );
+        assert!(column_names.insert(name));
+
+        let table_path = vec![path.clone(), vec![String::from("x")], path.clone()];
+        let name = naming.table_name(&root, &table_path);
+        assert_eq!(
+            naming.parse_table_name(&root, &name).as_ref(),
+            Some(&table_path),
+            "{}",
+            name
+        );
+        assert!(key_names.insert(naming.key_column_name(&root, &table_path)));
+    }
+    assert!(column_names.is_disjoint(&key_names));
+}
+
+#[test]
+fn test_naming_custom_separator() {
+    let naming = SeparatorNaming::new("/", "::").unwrap();
+    let path = vec![String::from("price"), String::from("amount")];
+    assert_eq!(naming.column_name(&path), "price/amount");
+    assert_eq!(
+        naming.table_name("root", &[vec![String::from("books")]]),
+        "root::books"
+    );
+    for path in tricky_paths() {
+        let name = naming.column_name(&path);
+        assert_eq!(naming.parse_column_name(&name), Some(path));
+    }
+
+    assert!(SeparatorNaming::new(".", ".").is_err());
+    assert!(SeparatorNaming::new("", ".").is_err());
+}
+
+#[test]
+fn test_naming_underscored() {
+    let naming = SeparatorNaming::underscored();
+    let path = vec![String::from("author"), String::from("firstName")];
+    assert_eq!(naming.column_name(&path), "author__firstName");
+    assert_eq!(
+        naming.table_name("root", &[vec![String::from("books")]]),
+        "root___books"
+    );
+}
+
+#[test]
+fn test_dialect_identifiers() {
+    let long = "a".repeat(100);
+    let id = Dialect::Postgres.identifier(&long);
+    assert_eq!(id.len(), 63);
+    assert_ne!(id, Dialect::Postgres.identifier(&"a".repeat(101)));
+    // Multibyte characters are not split by truncation
+    let id = Dialect::Postgres.identifier(&"я".repeat(40));
+    assert!(id.len() <= 63);
+
+    assert_eq!(Dialect::Bigquery.identifier("price.amount"), "price_amount");
+    assert_eq!(Dialect::Bigquery.identifier("1st"), "_1st");
+    assert_eq!(Dialect::Bigquery.identifier("select"), "c_select");
+    assert_eq!(Dialect::Bigquery.identifier("_TABLE_x"), "c__TABLE_x");
+    assert_eq!(Dialect::Generic.identifier("select"), "select");
+    assert_eq!(Dialect::Generic.identifier("a/b"), "a_b");
+
+    let used: HashSet<String> = vec![String::from("price")].into_iter().collect();
+    let id = Dialect::Mysql.unique_identifier("Price", &used);
+    assert_ne!(Dialect::Mysql.fold(&id), "price");
+    assert_eq!(Dialect::Postgres.unique_identifier("Price", &used), "Price");
+}
+
+#[test]
+fn test_dialect_schema() {
+    let long = "k".repeat(70);
+    let input = format!(
+        r#"{{"{long}1": 1, "{long}2": 2, "A": 3, "a": 4, "b.c": 5, "b": {{"c": 6}}, "select": 7}}"#,
+        long = long
+    );
+
+    let mut schema = DatabaseSchema::empty();
+    schema.set_dialect(Dialect::Bigquery);
+    let mut result = JsonValue::Object(Map::new());
+    let mut db = DatabaseJson::with_schema(schema, &mut result);
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+
+    let names = db
+        .get_schema()
+        .tables()
+        .flat_map(|table| table.columns.iter().map(|col| col.name().to_string()))
+        .collect::<Vec<_>>();
+    let folded = names
+        .iter()
+        .map(|name| name.to_lowercase())
+        .collect::<HashSet<_>>();
+    assert_eq!(folded.len(), names.len());
+    for name in names.iter() {
+        assert!(name.len() <= 300);
+        assert!(name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+    }
+    assert!(names.contains(&String::from("c_select")));
+
+    // Mapping from source paths to identifiers is kept in schema.json
+    let saved = serde_json::to_string(db.get_schema()).unwrap();
+    let loaded = DatabaseSchema::load(saved.as_bytes()).unwrap();
+    assert_eq!(loaded.dialect(), Dialect::Bigquery);
+    let table = loaded.table(&[]).unwrap();
+    let col = table.source_column(&vec![String::from("select")]).unwrap();
+    assert_eq!(col.name, "c_select");
+}
+
+#[test]
+fn test_format_registry() {
+    let path = output_dir("formats", "csv");
+    let mut options = FormatOptions::new(path.clone());
+    let mut db = open_database("csv", DatabaseSchema::empty(), &options).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+    let mut data_path = path.clone();
+    data_path.push("data");
+    assert_eq!(std::fs::read_dir(&data_path).unwrap().count(), 4);
+    std::fs::remove_dir_all(&path).unwrap();
+
+    assert!(open_database("unknown", DatabaseSchema::empty(), &options).is_err());
+    options.push("unknown=1").unwrap();
+    assert!(open_database("csv", DatabaseSchema::empty(), &options).is_err());
+    assert!(options.push("no-value").is_err());
+    assert!(formats().iter().any(|format| format.name == "csv"));
+}
+
+#[rstest]
+#[case("bookstore")]
+#[case("mixed-types")]
+#[case("xbus")]
+fn test_jsonl_matches_json(#[case] test_case: String) {
+    let path = output_dir("jsonl", &test_case);
+    let mut db = DatabaseJsonl::new(DatabaseSchema::empty(), path.clone()).unwrap();
+    read::read_to_db(&mut db, read_test_case(&test_case, false)).unwrap();
+    db.close().unwrap();
+
+    // Tables read back from lines hold the same typed values as the in-memory sink
+    let mut actual = Map::new();
+    for table in db.get_schema().tables() {
+        let mut data_path = path.clone();
+        data_path.push("data");
+        data_path.push(table.name.clone() + ".jsonl");
+        let records = std::fs::read_to_string(&data_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<JsonValue>(line).unwrap())
+            .collect::<Vec<_>>();
+        actual.insert(table.name.clone(), JsonValue::Array(records));
+    }
+    let expected = read_to_json(String::from("root"), read_test_case(&test_case, false)).unwrap();
+    assert_eq!(JsonValue::Object(actual), expected);
+
+    let mut schema_path = path.clone();
+    schema_path.push("schema.json");
+    assert!(DatabaseSchema::load_file(&schema_path).is_ok());
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_parquet_types() {
+    use parquet::basic::Type as PhysicalType;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    let path = output_dir("parquet", "bookstore");
+    let mut options = FormatOptions::new(path.clone());
+    options.push("row_group_size=2").unwrap();
+    let mut db = open_database("parquet", DatabaseSchema::empty(), &options).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let open_table = |name: &str| {
+        let mut data_path = path.clone();
+        data_path.push("data");
+        data_path.push(String::from(name) + ".parquet");
+        SerializedFileReader::new(File::open(&data_path).unwrap()).unwrap()
+    };
+
+    let reader = open_table("books_lin_root");
+    let columns = reader
+        .metadata()
+        .file_metadata()
+        .schema_descr()
+        .columns()
+        .to_vec();
+    let column_type = |name: &str| {
+        columns
+            .iter()
+            .find(|col| col.name() == name)
+            .unwrap()
+            .physical_type()
+    };
+    assert_eq!(column_type("id_books_lin_root"), PhysicalType::INT32);
+    assert_eq!(column_type("id_root"), PhysicalType::INT32);
+    assert_eq!(column_type("title"), PhysicalType::BYTE_ARRAY);
+    assert_eq!(column_type("rating"), PhysicalType::DOUBLE);
+    assert_eq!(column_type("used_in_inventory"), PhysicalType::INT64);
+
+    // Rows are split into row groups, values and nulls survive the round trip
+    let reader = open_table("genres_lin_books_lin_root");
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 5);
+    assert_eq!(reader.metadata().num_row_groups(), 3);
+    let subgenres = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|row| {
+            let row = row.unwrap();
+            let (_, subgenre) = row
+                .get_column_iter()
+                .find(|(name, _)| *name == "subgenre")
+                .unwrap();
+            subgenre.clone()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(subgenres[0], Field::Str(String::from("High fantasy")));
+    assert_eq!(subgenres[1], Field::Null);
+    assert_eq!(subgenres.len(), 5);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+/// Read avro container file written without compression into its schema and rows
+fn read_avro(path: &PathBuf) -> (JsonValue, Vec<Vec<JsonValue>>) {
+    fn long(data: &[u8], pos: &mut usize) -> i64 {
+        let (mut n, mut shift) = (0u64, 0);
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            n |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return (n >> 1) as i64 ^ -((n & 1) as i64);
+            }
+        }
+    }
+    fn bytes<'a>(data: &'a [u8], pos: &mut usize) -> &'a [u8] {
+        let len = long(data, pos) as usize;
+        *pos += len;
+        &data[*pos - len..*pos]
+    }
+    fn value(data: &[u8], pos: &mut usize, avro_type: &JsonValue) -> JsonValue {
+        match avro_type {
+            JsonValue::Array(union) => {
+                let branch = long(data, pos) as usize;
+                value(data, pos, &union[branch])
+            }
+            t if t == "null" => JsonValue::Null,
+            t if t == "boolean" => {
+                *pos += 1;
+                JsonValue::from(data[*pos - 1] == 1)
+            }
+            t if t == "long" => JsonValue::from(long(data, pos)),
+            t if t == "double" => {
+                *pos += 8;
+                JsonValue::from(f64::from_le_bytes(data[*pos - 8..*pos].try_into().unwrap()))
+            }
+            _ => JsonValue::from(String::from_utf8(bytes(data, pos).to_vec()).unwrap()),
+        }
+    }
+
+    let data = std::fs::read(path).unwrap();
+    assert_eq!(&data[..4], b"Obj\x01");
+    let mut pos = 4;
+    let mut meta = std::collections::HashMap::new();
+    loop {
+        let count = long(&data, &mut pos);
+        if count == 0 {
+            break;
+        }
+        for _ in 0..count {
+            let key = String::from_utf8(bytes(&data, &mut pos).to_vec()).unwrap();
+            meta.insert(key, bytes(&data, &mut pos).to_vec());
+        }
+    }
+    assert_eq!(meta["avro.codec"], b"null");
+    let schema: JsonValue = serde_json::from_slice(&meta["avro.schema"]).unwrap();
+    let sync = data[pos..pos + 16].to_vec();
+    pos += 16;
+
+    let mut rows = Vec::new();
+    while pos < data.len() {
+        let count = long(&data, &mut pos);
+        long(&data, &mut pos);
+        for _ in 0..count {
+            let row = schema["fields"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|field| value(&data, &mut pos, &field["type"]))
+                .collect();
+            rows.push(row);
+        }
+        assert_eq!(data[pos..pos + 16], sync[..]);
+        pos += 16;
+    }
+    (schema, rows)
+}
+
+#[test]
+fn test_avro_schema_growth() {
+    let path = output_dir("avro", "bookstore");
+    let mut options = FormatOptions::new(path.clone());
+    options.push("block_size=2").unwrap();
+    let mut db = open_database("avro", DatabaseSchema::empty(), &options).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let table_path = |name: &str| {
+        let mut data_path = path.clone();
+        data_path.push("data");
+        data_path.push(String::from(name) + ".avro");
+        data_path
+    };
+
+    let (schema, rows) = read_avro(&table_path("books_lin_root"));
+    assert_eq!(schema["type"], "record");
+    assert_eq!(schema["name"], "books_lin_root");
+    let field_type = |name: &str| {
+        schema["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|field| field["name"] == name)
+            .unwrap()["type"]
+            .clone()
+    };
+    assert_eq!(field_type("id_root"), "long");
+    assert_eq!(field_type("title"), "string");
+    assert_eq!(field_type("rating"), "double");
+    // Columns seen in only some records, or first seen after others, are nullable
+    assert_eq!(
+        field_type("idid_books"),
+        serde_json::json!(["null", "long"])
+    );
+    assert_eq!(field_type("bookId"), serde_json::json!(["null", "long"]));
+
+    assert_eq!(rows.len(), 2);
+    let column = |name: &str| {
+        let fields = schema["fields"].as_array().unwrap();
+        let col_id = fields.iter().position(|f| f["name"] == name).unwrap();
+        rows.iter()
+            .map(|row| row[col_id].clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(column("bookId"), vec![JsonValue::Null, JsonValue::from(2)]);
+    assert_eq!(column("title")[1], "The \"Alchemist\" 1\\2");
+
+    // Rows span several blocks
+    let (_, rows) = read_avro(&table_path("genres_lin_books_lin_root"));
+    assert_eq!(rows.len(), 5);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_avro_values() {
+    let write = |test_case: &str, input: &str| {
+        let path = output_dir("avro", test_case);
+        let options = FormatOptions::new(path.clone());
+        let mut db = open_database("avro", DatabaseSchema::empty(), &options).unwrap();
+        let result = read::read_to_db(&mut db, input.as_bytes()).and_then(|_| {
+            db.close()?;
+            Ok(std::fs::read(path.join("data").join("root.avro")).unwrap())
+        });
+        std::fs::remove_dir_all(&path).unwrap();
+        result
+    };
+
+    // Same input gives the same file
+    let input = r#"{"a": 1.5, "b": "x"}"#;
+    assert_eq!(
+        write("first", input).unwrap(),
+        write("second", input).unwrap()
+    );
+
+    // Numbers out of range of double are not written as defaults
+    let error = write("range", r#"{"a": 1.5, "b": 1e400}"#).unwrap_err();
+    assert!(matches!(error, Error::Sink(_)));
+    assert!(error.to_string().contains("1e400"));
+}
+
+#[cfg(feature = "xlsx")]
+#[test]
+fn test_xlsx_sheets() {
+    use calamine::{open_workbook, Data, Reader, Xlsx};
+
+    let path = output_dir("xlsx", "bookstore");
+    let mut options = FormatOptions::new(path.clone());
+    options.push("sheet_rows=2").unwrap();
+    let mut db = open_database("xlsx", DatabaseSchema::empty(), &options).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let mut workbook: Xlsx<_> = open_workbook(path.join("tables.xlsx")).unwrap();
+    assert_eq!(
+        workbook.sheet_names(),
+        vec![
+            "index",
+            "root",
+            "books_lin_root",
+            "genres_lin_books_lin_root",
+            "genres_lin_books_lin_root_2",
+            "genres_lin_books_lin_root_3",
+            "authors_lin_books_lin_root",
+        ]
+    );
+
+    let index = workbook.worksheet_range("index").unwrap();
+    let sheet_row = |name: &str| {
+        index
+            .rows()
+            .find(|row| row[0] == Data::String(String::from(name)))
+            .unwrap()
+            .to_vec()
+    };
+    let genres = sheet_row("genres_lin_books_lin_root_3");
+    assert_eq!(
+        genres[1],
+        Data::String(String::from("genres_lin_books_lin_root"))
+    );
+    assert_eq!(genres[3], Data::String(String::from("books_lin_root")));
+    // Rows are counted per sheet, 5 rows of the table are split 2, 2 and 1
+    assert_eq!(genres[4], Data::Float(1.0));
+    assert_eq!(sheet_row("genres_lin_books_lin_root")[4], Data::Float(2.0));
+
+    // Cells are typed by their values, header has all columns
+    let books = workbook.worksheet_range("books_lin_root").unwrap();
+    let header = books.rows().next().unwrap();
+    let col = |name: &str| header.iter().position(|h| h == name).unwrap();
+    let row = books.rows().nth(1).unwrap();
+    assert_eq!(
+        row[col("title")],
+        Data::String(String::from("The Lord of the Rings"))
+    );
+    assert_eq!(row[col("used_in_inventory")], Data::Float(10.0));
+    assert_eq!(row[col("bookId")], Data::Empty);
+    assert_eq!(
+        books.rows().nth(2).unwrap()[col("bookId")],
+        Data::Float(2.0)
+    );
+
+    let genres = workbook
+        .worksheet_range("genres_lin_books_lin_root_3")
+        .unwrap();
+    assert_eq!(genres.rows().count(), 2);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "xlsx")]
+#[test]
+fn test_xlsx_values() {
+    use calamine::{open_workbook, Data, Reader, Xlsx};
+
+    let path = output_dir("xlsx", "values");
+    let options = FormatOptions::new(path.clone());
+    let mut db = open_database("xlsx", DatabaseSchema::empty(), &options).unwrap();
+    let input = r#"{"small": -9007199254740992, "signed": -9223372036854775808,
+        "unsigned": 18446744073709551615, "huge": 123456789012345678901234567890,
+        "float": 2.5, "out_of_range": 1e400}"#;
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+
+    // Numbers that are not exact as excel numbers are written as text
+    let mut workbook: Xlsx<_> = open_workbook(path.join("tables.xlsx")).unwrap();
+    let root = workbook.worksheet_range("root").unwrap();
+    let header = root.rows().next().unwrap();
+    let row = root.rows().nth(1).unwrap();
+    let cell = |name: &str| row[header.iter().position(|h| h == name).unwrap()].clone();
+    assert_eq!(cell("small"), Data::Float(-9007199254740992.0));
+    assert_eq!(
+        cell("signed"),
+        Data::String(String::from("-9223372036854775808"))
+    );
+    assert_eq!(
+        cell("unsigned"),
+        Data::String(String::from("18446744073709551615"))
+    );
+    assert_eq!(
+        cell("huge"),
+        Data::String(String::from("123456789012345678901234567890"))
+    );
+    assert_eq!(cell("float"), Data::Float(2.5));
+    assert_eq!(cell("out_of_range"), Data::String(String::from("1e400")));
+    std::fs::remove_dir_all(&path).unwrap();
+
+    // Text longer than a cell holds is not cut
+    let path = output_dir("xlsx", "long");
+    let options = FormatOptions::new(path.clone());
+    let mut db = open_database("xlsx", DatabaseSchema::empty(), &options).unwrap();
+    let input = serde_json::json!({"text": "a".repeat(32_768)}).to_string();
+    let error = read::read_to_db(&mut db, input.as_bytes()).unwrap_err();
+    assert!(matches!(error, Error::Sink(_)));
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn test_arrow_batches() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int64Type;
+    use arrow_array::Array;
+    use arrow_ipc::reader::FileReader;
+    use arrow_schema::DataType;
+    use json_to_tables::database::database_arrow::ArrowOptions;
+    use json_to_tables::database::DatabaseArrow;
+
+    let path = output_dir("arrow", "bookstore");
+    let options = ArrowOptions { batch_size: 2 };
+    let mut db = DatabaseArrow::with_ipc(DatabaseSchema::empty(), path.clone(), options).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let (schema, batches) = db.batches("books_lin_root").unwrap();
+    let data_type = |name: &str| schema.field_with_name(name).unwrap().data_type().clone();
+    assert_eq!(data_type("id_books_lin_root"), DataType::Int32);
+    assert_eq!(data_type("title"), DataType::Utf8);
+    assert_eq!(data_type("rating"), DataType::Float64);
+    assert_eq!(data_type("used_in_inventory"), DataType::Int64);
+    assert!(!schema.field_with_name("id_root").unwrap().is_nullable());
+    let inventory = batches[0]
+        .column_by_name("used_in_inventory")
+        .unwrap()
+        .as_primitive::<Int64Type>();
+    assert_eq!(inventory.len(), batches[0].num_rows());
+
+    // Rows are split into batches, ipc file holds the same batches
+    let (_, batches) = db.batches("genres_lin_books_lin_root").unwrap();
+    assert_eq!(batches.len(), 3);
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 5);
+    let subgenres = batches[0]
+        .column_by_name("subgenre")
+        .unwrap()
+        .as_string::<i32>();
+    assert_eq!(subgenres.value(0), "High fantasy");
+    assert!(subgenres.is_null(1));
+
+    let mut data_path = path.clone();
+    data_path.push("data");
+    data_path.push("genres_lin_books_lin_root.arrow");
+    let reader = FileReader::try_new(File::open(&data_path).unwrap(), None).unwrap();
+    let read_batches = reader.map(|batch| batch.unwrap()).collect::<Vec<_>>();
+    assert_eq!(read_batches.as_slice(), batches);
+
+    let tables = db.into_batches();
+    assert!(tables.contains_key("root"));
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sqlite_keys() {
+    use rusqlite::Connection;
+
+    let path = output_dir("sqlite", "bookstore");
+    let mut options = FormatOptions::new(path.clone());
+    options.push("batch_size=3").unwrap();
+    let mut db = open_database("sqlite", DatabaseSchema::empty(), &options).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let mut db_path = path.clone();
+    db_path.push("database.sqlite");
+    let conn = Connection::open(&db_path).unwrap();
+    let count = |table: &str| -> i64 {
+        conn.query_row(&format!("SELECT count(*) FROM \"{}\"", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+    assert_eq!(count("root"), 1);
+    assert_eq!(count("books_lin_root"), 2);
+    assert_eq!(count("genres_lin_books_lin_root"), 5);
+
+    // Columns appearing in later records were added to the table
+    let subgenres: Vec<Option<String>> = conn
+        .prepare("SELECT subgenre FROM genres_lin_books_lin_root ORDER BY 1 DESC")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect();
+    assert_eq!(subgenres[0].as_deref(), Some("High fantasy"));
+
+    let (parent, from, to): (String, String, String) = conn
+        .query_row(
+            "SELECT \"table\", \"from\", \"to\" FROM pragma_foreign_key_list('genres_lin_books_lin_root')",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(parent, "books_lin_root");
+    assert_eq!(from, "id_books_lin_root");
+    assert_eq!(to, "id_books_lin_root");
+
+    let violations: i64 = conn
+        .query_row("SELECT count(*) FROM pragma_foreign_key_check", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(violations, 0);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sqlite_mixed_types() {
+    use rusqlite::Connection;
+
+    let path = output_dir("sqlite", "mixed");
+    let options = FormatOptions::new(path.clone());
+    let mut db = open_database("sqlite", DatabaseSchema::empty(), &options).unwrap();
+    let input = r#"[{"code": 5, "price": 1}, {"code": "012", "price": 2.5}]"#;
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+
+    let conn = Connection::open(path.join("database.sqlite")).unwrap();
+    let types: Vec<(String, String)> = conn
+        .prepare(
+            "SELECT name, type FROM pragma_table_info('list_lin_root') WHERE name NOT LIKE 'id_%'",
+        )
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect();
+    assert_eq!(
+        types,
+        vec![
+            (String::from("code"), String::from("TEXT")),
+            (String::from("price"), String::from("REAL"))
+        ]
+    );
+
+    // Values keep their form, earlier ones follow the final type
+    let values: Vec<(String, f64)> = conn
+        .prepare("SELECT code, price FROM list_lin_root ORDER BY 1 DESC")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect();
+    assert_eq!(
+        values,
+        vec![(String::from("5"), 1.0), (String::from("012"), 2.5)]
+    );
+
+    // Transactions need at least one row
+    let mut options = FormatOptions::new(path.clone());
+    options.push("batch_size=0").unwrap();
+    assert!(open_database("sqlite", DatabaseSchema::empty(), &options).is_err());
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[rstest]
+#[case(r#"[{"a": 1, "c": 1}, {"a": 2, "b": 3, "c": 1}, {"c": 2}]"#, &["a", "b"], 3)]
+#[case(r#"[{"a": 1, "c": 1}, {"a": null, "c": 2}]"#, &["a"], 2)]
+#[case(r#"[{"a": 1, "c": 1}, {"c": 2, "a": 2}]"#, &[], 2)]
+fn test_nullable_columns(#[case] input: &str, #[case] nullable: &[&str], #[case] records: usize) {
+    let mut result = JsonValue::Object(Map::new());
+    let mut db = DatabaseJson::new(String::from("root"), &mut result);
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+    let table = db
+        .get_schema()
+        .tables()
+        .find(|table| !table.path.is_empty())
+        .unwrap();
+
+    // Columns missing in some records, before or after their first value, hold nulls
+    let actual = table
+        .columns
+        .iter()
+        .filter_map(|col| match col {
+            ColumnSchema::SourceColumn(col) if col.is_nullable => Some(col.name.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(actual, nullable);
+    assert_eq!(table.records, records);
+}
+
+#[test]
+fn test_ddl() {
+    let input = r#"{"name": "a", "items": [{"n": 1, "flag": true}, {"n": 2.5, "note": null}]}"#;
+    let mut schema = DatabaseSchema::empty();
+    schema.set_dialect(Dialect::Postgres);
+    let mut result = JsonValue::Object(Map::new());
+    let mut db = DatabaseJson::with_schema(schema, &mut result);
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+    let schema = db.get_schema();
+
+    let ddl = ddl::create_tables(schema).unwrap();
+    // Parents come first, types and nullability follow the values
+    assert!(ddl.find("CREATE TABLE \"root\"").unwrap() < ddl.find("\"items_lin_root\"").unwrap());
+    assert!(ddl.contains("\"n\" DOUBLE PRECISION NOT NULL"));
+    assert!(ddl.contains("\"flag\" BOOLEAN,"));
+    assert!(ddl.contains("\"note\" TEXT,"));
+    assert!(ddl.contains("FOREIGN KEY (\"id_root\") REFERENCES \"root\" (\"id_root\")"));
+
+    let script = ddl::load_script(schema).unwrap();
+    assert!(script.contains("FROM 'data/items_lin_root.csv' WITH (FORMAT csv, HEADER true)"));
+
+    assert!(ddl::create_tables(&DatabaseSchema::empty()).is_err());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_ddl_sqlite() {
+    let mut schema = DatabaseSchema::empty();
+    schema.set_dialect(Dialect::Sqlite);
+    let mut result = JsonValue::Object(Map::new());
+    let mut db = DatabaseJson::with_schema(schema, &mut result);
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.execute_batch(&ddl::create_tables(db.get_schema()).unwrap())
+        .unwrap();
+    let tables: i64 = conn
+        .query_row(
+            "SELECT count(*) FROM sqlite_master WHERE type = 'table'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(tables, 4);
+
+    // Fields imported as text are converted like values of the sqlite sink
+    let input = r#"[{"flag": true, "note": "x"}, {"flag": false}, {"note": null}]"#;
+    let mut schema = DatabaseSchema::empty();
+    schema.set_dialect(Dialect::Sqlite);
+    let mut result = JsonValue::Object(Map::new());
+    let mut db = DatabaseJson::with_schema(schema, &mut result);
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.execute_batch(&ddl::create_tables(db.get_schema()).unwrap())
+        .unwrap();
+    conn.execute_batch(
+        "INSERT INTO root (id_root) VALUES (0);
+        INSERT INTO list_lin_root VALUES (0, 0, 'true', 'x'), (1, 0, 'false', ''), (2, 0, '', '');",
+    )
+    .unwrap();
+    let script = ddl::load_script(db.get_schema()).unwrap();
+    assert!(script.contains(".import --csv --skip 1 'data/list_lin_root.csv' \"list_lin_root\""));
+    let updates = script
+        .lines()
+        .filter(|line| !line.starts_with('.'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    conn.execute_batch(&updates).unwrap();
+    let values = conn
+        .prepare("SELECT flag, note FROM list_lin_root ORDER BY id_list_lin_root")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect::<Vec<(Option<i64>, Option<String>)>>();
+    assert_eq!(
+        values,
+        vec![
+            (Some(1), Some(String::from("x"))),
+            (Some(0), None),
+            (None, None)
+        ]
+    );
+}
+
+/// Needs a database the test may write to, run with
+/// `JSON_TO_TABLES_POSTGRES_URL=postgres://user@localhost/db cargo test -- --ignored postgres`
+#[cfg(feature = "postgres")]
+#[test]
+#[ignore = "needs JSON_TO_TABLES_POSTGRES_URL of a local postgres"]
+fn test_postgres_copy() {
+    let url = std::env::var("JSON_TO_TABLES_POSTGRES_URL")
+        .expect("JSON_TO_TABLES_POSTGRES_URL must point to a test database");
+    let mut client = postgres::Client::connect(&url, postgres::NoTls).unwrap();
+    client
+        .batch_execute(
+            "DROP SCHEMA IF EXISTS json_to_tables_test CASCADE; CREATE SCHEMA json_to_tables_test",
+        )
+        .unwrap();
+
+    // Later records add columns and change column types
+    let input = r#"[{"a": 1, "c": 1.0, "d": 1e20, "items": [{"x": true}]}, {"a": 2.5, "b": "text", "c": "x", "d": "y", "items": [{"x": "no"}, {}]}]"#;
+    let path = output_dir("postgres", "copy");
+    let mut options = FormatOptions::new(path.clone());
+    options.push(&format!("url={}", url)).unwrap();
+    options.push("schema=json_to_tables_test").unwrap();
+    options.push("batch_size=1").unwrap();
+    let mut db = open_database("postgres", DatabaseSchema::empty(), &options).unwrap();
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+
+    let rows = client
+        .query(
+            "SELECT a::text, b FROM json_to_tables_test.list_lin_root ORDER BY id_list_lin_root",
+            &[],
+        )
+        .unwrap();
+    let values = rows
+        .iter()
+        .map(|row| (row.get::<_, String>(0), row.get::<_, Option<String>>(1)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        values,
+        vec![
+            (String::from("1"), None),
+            (String::from("2.5"), Some(String::from("text")))
+        ]
+    );
+
+    let xs = client
+        .query(
+            "SELECT x FROM json_to_tables_test.items_lin_list_lin_root ORDER BY 1",
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| row.get::<_, Option<String>>(0))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        xs,
+        vec![Some(String::from("no")), Some(String::from("true")), None]
+    );
+
+    // Rows copied before columns widened to text keep their source text
+    let texts = client
+        .query(
+            "SELECT c, d FROM json_to_tables_test.list_lin_root ORDER BY id_list_lin_root",
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| (row.get::<_, String>(0), row.get::<_, String>(1)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        texts,
+        vec![
+            (String::from("1.0"), String::from("1e20")),
+            (String::from("x"), String::from("y"))
+        ]
+    );
+
+    // Foreign keys are in place once loading is finished
+    let constraints: i64 = client
+        .query_one(
+            "SELECT count(*) FROM information_schema.table_constraints \
+             WHERE table_schema = 'json_to_tables_test' AND constraint_type = 'FOREIGN KEY'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(constraints, 2);
+
+    client
+        .batch_execute("DROP SCHEMA json_to_tables_test CASCADE")
+        .unwrap();
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sql_inserts() {
+    let input = r#"{"s": "it's \"quoted\"\nline", "items": [{"b": true}, {"b": false, "n": 1.5}]}"#;
+    let path = output_dir("sql", "combined");
+    let mut schema = DatabaseSchema::empty();
+    schema.set_dialect(Dialect::Sqlite);
+    let mut options = FormatOptions::new(path.clone());
+    options.push("combined=true").unwrap();
+    options.push("batch_size=1").unwrap();
+    let mut db = open_database("sql", schema, &options).unwrap();
+    read::read_to_db(&mut db, input.as_bytes()).unwrap();
+    db.close().unwrap();
+
+    // Script runs after the generated DDL with foreign keys enforced
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+    conn.execute_batch(&ddl::create_tables(db.get_schema()).unwrap())
+        .unwrap();
+    let mut data_path = path.clone();
+    data_path.push("data.sql");
+    conn.execute_batch(&std::fs::read_to_string(&data_path).unwrap())
+        .unwrap();
+
+    let s: String = conn
+        .query_row("SELECT s FROM root", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(s, "it's \"quoted\"\nline");
+    let rows: Vec<(i64, Option<f64>)> = conn
+        .prepare("SELECT b, n FROM items_lin_root ORDER BY id_items_lin_root")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    assert_eq!(rows, vec![(1, None), (0, Some(1.5))]);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_tee_formats() {
+    let path = output_dir("tee", "bookstore");
+    let mut options = FormatOptions::new(path.clone());
+    options.push("sqlite.file=books.sqlite").unwrap();
+    let mut db = open_database("csv,sqlite", DatabaseSchema::empty(), &options).unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+
+    // Both sinks follow the dialect of sqlite, so csv files match its table names
+    assert_eq!(db.get_schema().dialect(), Dialect::Sqlite);
+    let conn = rusqlite::Connection::open(path.join("sqlite").join("books.sqlite")).unwrap();
+    let csv_rows = std::fs::read_to_string(path.join("csv/data/books_lin_root.csv"))
+        .unwrap()
+        .lines()
+        .count();
+    let sqlite_rows: usize = conn
+        .query_row("SELECT count(*) FROM books_lin_root", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(csv_rows, sqlite_rows + 1);
+
+    // Sinks write the same schema, whichever of them comes first
+    let schema_json = |path: &PathBuf, sink: &str| {
+        std::fs::read_to_string(path.join(sink).join("schema.json")).unwrap()
+    };
+    assert_eq!(schema_json(&path, "csv"), schema_json(&path, "sqlite"));
+    let reversed_path = output_dir("tee", "reversed");
+    let mut db = open_database(
+        "sqlite,csv",
+        DatabaseSchema::empty(),
+        &FormatOptions::new(reversed_path.clone()),
+    )
+    .unwrap();
+    read::read_to_db(&mut db, read_test_case("bookstore", false)).unwrap();
+    db.close().unwrap();
+    assert_eq!(
+        schema_json(&path, "csv"),
+        schema_json(&reversed_path, "csv")
+    );
+    std::fs::remove_dir_all(&reversed_path).unwrap();
+
+    let mut options = FormatOptions::new(path.clone());
+    options.push("file=x").unwrap();
+    options.push("unknown=1").unwrap();
+    assert!(open_database("csv,sqlite", DatabaseSchema::empty(), &options).is_err());
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+/// Sink failing on every write
+struct FailingDatabase {
+    schema: DatabaseSchema,
+}
+
+impl Database for FailingDatabase {
+    fn get_schema(&self) -> &DatabaseSchema {
+        &self.schema
+    }
+
+    fn get_schema_mut(&mut self) -> &mut DatabaseSchema {
+        &mut self.schema
+    }
+
+    fn write(&mut self, _: TableLocation, _: TableRecord) -> Result<(), Error> {
+        Err(Error::Sink(anyhow::anyhow!("Disk is full")))
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tee_reports_failed_sink() {
+    let path = output_dir("tee", "failing");
+    let jsonl = DatabaseJsonl::new(DatabaseSchema::empty(), path.clone()).unwrap();
+    let failing = FailingDatabase {
+        schema: DatabaseSchema::empty(),
+    };
+    let mut db = DatabaseTee::new(vec![
+        (String::from("jsonl"), Box::new(jsonl) as Box<dyn Database>),
+        (String::from("archive"), Box::new(failing)),
+    ])
+    .unwrap();
+    let error = read::read_to_db(&mut db, r#"{"a": 1}"#.as_bytes()).unwrap_err();
+    assert!(format!("{:#}", error).contains("Sink archive failed: Disk is full"));
+    db.close().unwrap();
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_tee_schema_changes() {
+    let path = output_dir("tee", "schema");
+    let sink = |name: &str| {
+        let mut sink_path = path.clone();
+        sink_path.push(name);
+        std::fs::create_dir_all(&sink_path).unwrap();
+        let jsonl = DatabaseJsonl::new(DatabaseSchema::empty(), sink_path).unwrap();
+        (String::from(name), Box::new(jsonl) as Box<dyn Database>)
+    };
+    let mut db = DatabaseTee::new(vec![sink("first"), sink("second")]).unwrap();
+    db.for_each_schema_mut(|schema| {
+        schema.set_naming(naming_strategy("underscored").unwrap());
+    });
+    read::read_to_db(&mut db, r#"{"a": {"b": [1]}}"#.as_bytes()).unwrap();
+    db.close().unwrap();
+    let names = db
+        .get_schema()
+        .tables()
+        .map(|table| table.name.clone())
+        .collect::<HashSet<_>>();
+    assert!(names.contains("root___a__b"), "{:?}", names);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+#[should_panic]
+fn test_tee_rejects_schema_of_one_sink() {
+    let path = output_dir("tee", "schema-of-one");
+    let sink = DatabaseJsonl::new(DatabaseSchema::empty(), path).unwrap();
+    let mut db = DatabaseTee::new(vec![(
+        String::from("jsonl"),
+        Box::new(sink) as Box<dyn Database>,
+    )])
+    .unwrap();
+    db.get_schema_mut().set_dialect(Dialect::Sqlite);
+}
+
+#[test]
+fn test_error_locations() {
+    let locate = |input: &str, jobs: usize, failing: bool| {
+        let options = read::ReadOptions {
+            jobs,
+            chunk_size: 1,
+            ..read::ReadOptions::default()
+        };
+        let readers = vec![("input", input.as_bytes())];
+        let error = if failing {
+            let mut db = FailingDatabase {
+                schema: DatabaseSchema::empty(),
+            };
+            read::read_to_db_many_parallel(&mut db, readers, &options, &mut |_, _| {})
+        } else {
+            let mut result = JsonValue::Object(Map::new());
+            let mut db = DatabaseJson::new(String::from("root"), &mut result);
+            read::read_to_db_many_parallel(&mut db, readers, &options, &mut |_, _| {})
+        }
+        .unwrap_err();
+        let location = error.location().unwrap();
+        (
+            location.position.line,
+            location.position.column,
+            location.pointer.clone(),
+        )
+    };
+
+    let input = "[{\"a\": 1},\n {\"a\": [1, {\"b\": tru}]}]";
+    for jobs in [1, 3] {
+        assert_eq!(
+            locate(input, jobs, false),
+            (2, 18, String::from("/1/a/1/b"))
+        );
+    }
+
+    let input = "{\"x\": 1,\n \"a/b\": [\n  {\"c\": 2}]}";
+    for jobs in [1, 3] {
+        assert_eq!(locate(input, jobs, true), (3, 10, String::from("/a~1b/0")));
+    }
+
+    // Whitespace skipped before looking for an array still counts
+    let input = "\n\n\n{\"a\": [1, {\"b\": tru}]}";
+    for jobs in [1, 3] {
+        assert_eq!(locate(input, jobs, false), (4, 17, String::from("/a/1/b")));
+    }
+}
+
+/// Reader failing on every read
+struct FailingReader;
+
+impl Read for FailingReader {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("Connection reset"))
+    }
+}
+
+#[test]
+fn test_error_kinds() {
+    let mut result = JsonValue::Object(Map::new());
+    let db = DatabaseJson::new(String::from("root"), &mut result);
+    let error = read::read_to_db(db, r#"{"a": [1, }"#.as_bytes()).unwrap_err();
+    assert!(matches!(error, Error::Parse(_)));
+    assert_eq!(error.location().unwrap().pointer, "/a/1");
+
+    let db = DatabaseJson::new(String::from("root"), &mut result);
+    let error = read::read_to_db(db, BufReader::new(FailingReader)).unwrap_err();
+    assert!(matches!(error, Error::Io(_)));
+
+    let options = read::ReadOptions {
+        jobs: 2,
+        ..read::ReadOptions::default()
+    };
+    let readers = vec![("input", BufReader::new(FailingReader))];
+    let mut db = DatabaseJson::new(String::from("root"), &mut result);
+    let error =
+        read::read_to_db_many_parallel(&mut db, readers, &options, &mut |_, _| {}).unwrap_err();
+    assert!(matches!(error, Error::Io(_)));
+
+    let db = FailingDatabase {
+        schema: DatabaseSchema::empty(),
+    };
+    let error = read::read_to_db(db, r#"{"a": 1}"#.as_bytes()).unwrap_err();
+    assert!(matches!(error, Error::Sink(_)));
+    assert!(format!("{:#}", error).contains("Disk is full"));
+
+    // Records of other sources may hold values that don't fit a csv table
+    let path = output_dir("errors", "csv");
+    let mut db = DatabaseCsv::new(DatabaseSchema::empty(), path.clone()).unwrap();
+    let loc = TableLocation {
+        table_path: Vec::new(),
+        object_id: 0,
+        parent_object_id: 0,
+    };
+    let mut rec = TableRecord::new();
+    rec.insert(vec![String::from("a")], serde_json::json!([1, 2]));
+    let error = db.write(loc.clone(), rec).unwrap_err();
+    assert!(matches!(error, Error::Schema(_)));
+    db.close().unwrap();
+    assert!(matches!(db.close().unwrap_err(), Error::Schema(_)));
+    std::fs::remove_dir_all(&path).unwrap();
+
+    let mut db = json_to_tables::database::DatabaseStdout::new();
+    assert_eq!(db.get_schema().tables().count(), 0);
+    db.close().unwrap();
+}
+
+#[rstest]
+#[case(2)]
+#[case(8)]
+fn test_parallel_files_match_sequential(#[case] jobs: usize) {
+    let cases = ["bookstore", "stations", "bookstore", "pyramids", "xbus"];
+    let read_all = |jobs: usize| {
+        let mut result = JsonValue::Object(Map::new());
+        let mut db = DatabaseJson::new(String::from("root"), &mut result);
+        let readers = cases
+            .iter()
+            .map(|case| (case.to_string(), read_test_case(case, false)))
+            .collect::<Vec<_>>();
+        let mut parsed = Vec::new();
+        let options = read::ReadOptions {
+            jobs,
+            ..read::ReadOptions::default()
+        };
+        read::read_to_db_many_parallel(&mut db, readers, &options, &mut |case, records| {
+            parsed.push((case, records))
+        })
+        .unwrap();
+        db.close().unwrap();
+        (result, parsed)
+    };
+
+    let (expected, expected_parsed) = read_all(1);
+    let (actual, parsed) = read_all(jobs);
+    assert_eq!(actual, expected);
+    assert_eq!(parsed, expected_parsed);
+    assert_eq!(parsed[0].0, "bookstore");
+    assert_eq!(parsed[0].1, parsed[2].1);
+}
+
+#[test]
+fn test_parallel_files_report_error() {
+    let mut result = JsonValue::Object(Map::new());
+    let mut db = DatabaseJson::new(String::from("root"), &mut result);
+    let readers = vec![
+        ("good", read_test_case("bookstore", false)),
+        ("bad", BufReader::new(File::open(file!()).unwrap())),
+        ("good", read_test_case("bookstore", false)),
+    ];
+    let options = read::ReadOptions {
+        jobs: 2,
+        ..read::ReadOptions::default()
+    };
+    let mut parsed = Vec::new();
+    let result = read::read_to_db_many_parallel(&mut db, readers, &options, &mut |name, _| {
+        parsed.push(name)
+    });
+    assert!(result.is_err());
+    assert_eq!(parsed, vec!["good"]);
+}
+
+#[rstest]
+#[case("pyramids", 1)]
+#[case("pyramids", 200)]
+#[case("mixed-types", 1)]
+#[case("list-empty-dicts", 1)]
+#[case("empty_list", 1)]
+#[case("bookstore", 1)]
+#[case("trailing-comma", 1)]
+#[case("trailing-comma", 8)]
+fn test_chunked_array_matches_sequential(#[case] case: &str, #[case] chunk_size: usize) {
+    let read_all = |options: &read::ReadOptions| {
+        let mut result = JsonValue::Object(Map::new());
+        let mut db = DatabaseJson::new(String::from("root"), &mut result);
+        let readers = vec![
+            ("first", read_test_case(case, false)),
+            ("second", read_test_case(case, false)),
+        ];
+        let mut parsed = Vec::new();
+        // Invalid input must fail the same way whether split or not,
+        // though records written before the error may differ
+        let outcome =
+            read::read_to_db_many_parallel(&mut db, readers, options, &mut |name, records| {
+                parsed.push((name, records))
+            })
+            .map(|()| db.close().unwrap())
+            .map_err(|e| format!("{:#}", e));
+        (outcome.map(|()| result), parsed)
+    };
+
+    let expected = read_all(&read::ReadOptions::default());
+    let actual = read_all(&read::ReadOptions {
+        jobs: 4,
+        chunk_size,
+        ..read::ReadOptions::default()
+    });
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn test_chunked_json_lines() {
+    let lines = "{\"a\": 1, \"b\": [{\"c\": 2}]}\n\n{\"a\": \"x\", \"b\": []}\n[3, {\"d\": 4}]\n";
+    let read_all = |options: &read::ReadOptions| {
+        let mut result = JsonValue::Object(Map::new());
+        let mut db = DatabaseJson::new(String::from("root"), &mut result);
+        let readers = vec![((), lines.as_bytes()), ((), lines.as_bytes())];
+        read::read_to_db_many_parallel(&mut db, readers, options, &mut |_, _| {}).unwrap();
+        db.close().unwrap();
+        result
+    };
+
+    let expected = read_all(&read::ReadOptions {
+        json_lines: true,
+        ..read::ReadOptions::default()
+    });
+    let actual = read_all(&read::ReadOptions {
+        jobs: 3,
+        chunk_size: 1,
+        json_lines: true,
+    });
+    assert_eq!(actual, expected);
+    assert_eq!(
+        expected["root"],
+        serde_json::json!([{"a": 1, "id_root": 0}, {"a": "x", "id_root": 1}, {"id_root": 2},
+            {"a": 1, "id_root": 3}, {"a": "x", "id_root": 4}, {"id_root": 5}])
+    );
+}
+
+#[test]
+fn test_concatenated_documents() {
+    let documents = "{\"a\": 1,\n \"b\": [{\"c\": \"}\\n\"}]}\n{\"a\": 2} 3\n\n[{\"c\": 4}]";
+    let mut expected = JsonValue::Object(Map::new());
+    read::read_lines_to_db(
+        DatabaseJson::new(String::from("root"), &mut expected),
+        documents.as_bytes(),
+    )
+    .unwrap();
+    let root_ids = expected["root"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rec| rec["id_root"].as_i64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(root_ids, vec![0, 1, 2, 3]);
+
+    // Chunks are cut between documents only
+    let mut actual = JsonValue::Object(Map::new());
+    let mut db = DatabaseJson::new(String::from("root"), &mut actual);
+    let options = read::ReadOptions {
+        jobs: 2,
+        chunk_size: 1,
+        json_lines: true,
+    };
+    read::read_to_db_many_parallel(
+        &mut db,
+        vec![((), documents.as_bytes())],
+        &options,
+        &mut |_, _| {},
+    )
+    .unwrap();
+    db.close().unwrap();
+    assert_eq!(actual, expected);
+
+    let mut single = JsonValue::Object(Map::new());
+    assert!(read::read_to_db(
+        DatabaseJson::new(String::from("root"), &mut single),
+        documents.as_bytes()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_tolerant_rejects() {
+    let read_tolerant = |input: &str, options: &read::ReadOptions| {
+        let mut result = JsonValue::Object(Map::new());
+        let mut rejects = Vec::new();
+        let mut db = DatabaseJson::new(String::from("root"), &mut result);
+        read::read_to_db_many_tolerant(
+            &mut db,
+            vec![("input", input.as_bytes())],
+            options,
+            &mut |name, reject| {
+                assert_eq!(*name, "input");
+                rejects.push((
+                    reject.position.offset,
+                    String::from_utf8(reject.bytes).unwrap(),
+                ));
+                Ok(())
+            },
+            &mut |_, _| {},
+        )
+        .unwrap();
+        db.close().unwrap();
+        (result, rejects)
+    };
+    let lines = read::ReadOptions {
+        json_lines: true,
+        ..read::ReadOptions::default()
+    };
+    let chunked = |options: read::ReadOptions| read::ReadOptions {
+        jobs: 3,
+        chunk_size: 1,
+        ..options
+    };
+
+    let input =
+        "{\"a\": 1, \"b\": [{\"c\": 2}]}\n{\"a\": [1\nnot json\n{\"a\": 3, \"b\": [{\"c\": 4}]}\n";
+    let (result, rejects) = read_tolerant(input, &lines);
+    assert_eq!(
+        rejects,
+        vec![
+            (26, String::from("{\"a\": [1")),
+            (35, String::from("not json"))
+        ]
+    );
+    assert_eq!(
+        result["root"],
+        serde_json::json!([{"a": 1, "id_root": 0}, {"a": 3, "id_root": 1}])
+    );
+    assert_eq!(
+        result["b_lin_root"],
+        serde_json::json!([{"c": 2, "id_b_lin_root": 0, "id_root": 0},
+            {"c": 4, "id_b_lin_root": 1, "id_root": 1}])
+    );
+    assert_eq!(read_tolerant(input, &chunked(lines)), (result, rejects));
+
+    let input = "[{\"a\": 1}, {\"a\": }, 2,, {\"a\": 3}] x";
+    let (result, rejects) = read_tolerant(input, &read::ReadOptions::default());
+    assert_eq!(
+        rejects,
+        vec![
+            (11, String::from("{\"a\": }")),
+            (22, String::new()),
+            (34, String::from("x"))
+        ]
+    );
+    assert_eq!(result["root"], serde_json::json!([{"id_root": 0}]));
+    assert_eq!(result["list_lin_root"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        read_tolerant(input, &chunked(read::ReadOptions::default())),
+        (result, rejects)
+    );
+}